@@ -0,0 +1,4 @@
+REVOKE UPDATE ON permissions FROM natter_api_user;
+
+UPDATE permissions SET perms = replace(perms, 'a', '');
+ALTER TABLE permissions ALTER COLUMN perms TYPE VARCHAR(3);
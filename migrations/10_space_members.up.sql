@@ -0,0 +1,6 @@
+ALTER TABLE permissions ALTER COLUMN perms TYPE VARCHAR(4);
+UPDATE permissions SET perms = 'rwda'
+    FROM spaces
+    WHERE permissions.space_id = spaces.space_id AND permissions.user_id = spaces.owner;
+
+GRANT UPDATE ON permissions TO natter_api_user;
@@ -0,0 +1,10 @@
+REVOKE SELECT ON role_permissions FROM natter_api_user;
+
+ALTER TABLE permissions ADD COLUMN perms VARCHAR(4) NULL;
+UPDATE permissions SET perms = role_permissions.perms
+    FROM role_permissions
+    WHERE permissions.role = role_permissions.role;
+ALTER TABLE permissions ALTER COLUMN perms SET NOT NULL;
+ALTER TABLE permissions DROP COLUMN role;
+
+DROP TABLE role_permissions;
@@ -0,0 +1,46 @@
+CREATE TABLE role_permissions(
+    role VARCHAR(30) NOT NULL PRIMARY KEY,
+    perms VARCHAR(4) NOT NULL
+);
+
+INSERT INTO role_permissions (role, perms) VALUES
+    ('owner', 'rwda'),
+    ('moderator', 'rwd'),
+    ('member', 'rw'),
+    ('observer', 'r');
+
+ALTER TABLE permissions ADD COLUMN role VARCHAR(30) NULL REFERENCES role_permissions(role);
+UPDATE permissions SET role = (
+    SELECT role_permissions.role FROM role_permissions
+    WHERE translate(role_permissions.perms, permissions.perms, '') = ''
+        AND translate(permissions.perms, role_permissions.perms, '') = ''
+);
+-- Grants that match no role are narrowed to the largest role they still cover,
+-- so the migration never widens access. Grants no role fits within are removed.
+DO $$
+DECLARE
+    unmatched RECORD;
+    closest VARCHAR(30);
+BEGIN
+    FOR unmatched IN SELECT space_id, user_id, perms FROM permissions WHERE role IS NULL LOOP
+        SELECT role INTO closest FROM role_permissions
+            WHERE translate(role_permissions.perms, unmatched.perms, '') = ''
+            ORDER BY length(role_permissions.perms) DESC
+            LIMIT 1;
+        IF closest IS NULL THEN
+            RAISE WARNING 'removing permissions % of % on space %, which no role fits within',
+                unmatched.perms, unmatched.user_id, unmatched.space_id;
+            DELETE FROM permissions
+                WHERE space_id = unmatched.space_id AND user_id = unmatched.user_id;
+        ELSE
+            RAISE WARNING 'narrowing permissions % of % on space % to role %',
+                unmatched.perms, unmatched.user_id, unmatched.space_id, closest;
+            UPDATE permissions SET role = closest
+                WHERE space_id = unmatched.space_id AND user_id = unmatched.user_id;
+        END IF;
+    END LOOP;
+END $$;
+ALTER TABLE permissions ALTER COLUMN role SET NOT NULL;
+ALTER TABLE permissions DROP COLUMN perms;
+
+GRANT SELECT ON role_permissions TO natter_api_user;
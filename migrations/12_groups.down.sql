@@ -0,0 +1,8 @@
+DELETE FROM permissions WHERE group_id IS NOT NULL;
+ALTER TABLE permissions DROP COLUMN group_id;
+ALTER TABLE permissions DROP CONSTRAINT permissions_space_id_user_id_key;
+ALTER TABLE permissions ALTER COLUMN user_id SET NOT NULL;
+ALTER TABLE permissions ADD PRIMARY KEY (space_id, user_id);
+
+DROP TABLE group_members;
+DROP TABLE groups;
@@ -0,0 +1,27 @@
+CREATE TABLE groups(
+    group_id VARCHAR(30) NOT NULL PRIMARY KEY,
+    owner VARCHAR(30) NOT NULL REFERENCES users(user_id),
+    created TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX groups_owner_idx ON groups(owner);
+
+CREATE TABLE group_members(
+    group_id VARCHAR(30) NOT NULL REFERENCES groups(group_id),
+    user_id VARCHAR(30) NULL REFERENCES users(user_id),
+    member_group_id VARCHAR(30) NULL REFERENCES groups(group_id),
+    CHECK ((user_id IS NULL) <> (member_group_id IS NULL)),
+    UNIQUE (group_id, user_id),
+    UNIQUE (group_id, member_group_id)
+);
+CREATE INDEX group_members_user_id_idx ON group_members(user_id);
+CREATE INDEX group_members_member_group_id_idx ON group_members(member_group_id);
+
+ALTER TABLE permissions DROP CONSTRAINT permissions_pkey;
+ALTER TABLE permissions ALTER COLUMN user_id DROP NOT NULL;
+ALTER TABLE permissions ADD COLUMN group_id VARCHAR(30) NULL REFERENCES groups(group_id);
+ALTER TABLE permissions ADD CHECK ((user_id IS NULL) <> (group_id IS NULL));
+ALTER TABLE permissions ADD UNIQUE (space_id, user_id);
+ALTER TABLE permissions ADD UNIQUE (space_id, group_id);
+
+GRANT SELECT, INSERT, UPDATE, DELETE ON groups TO natter_api_user;
+GRANT SELECT, INSERT, DELETE ON group_members TO natter_api_user;
@@ -0,0 +1,12 @@
+CREATE TABLE capabilities(
+    token_hash VARCHAR(100) PRIMARY KEY,
+    space_id INT NOT NULL REFERENCES spaces(space_id),
+    perms VARCHAR(4) NOT NULL,
+    created_by VARCHAR(30) NOT NULL REFERENCES users(user_id),
+    expiry TIMESTAMPTZ NOT NULL,
+    created TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX capabilities_space_id_idx ON capabilities(space_id);
+CREATE INDEX capabilities_expiry_idx ON capabilities(expiry);
+
+GRANT SELECT, INSERT, DELETE ON capabilities TO natter_api_user;
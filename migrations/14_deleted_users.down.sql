@@ -0,0 +1,2 @@
+REVOKE DELETE ON spaces FROM natter_api_user;
+DROP TABLE IF EXISTS deleted_users;
@@ -0,0 +1,7 @@
+CREATE TABLE deleted_users (
+    user_id VARCHAR(30) PRIMARY KEY,
+    deleted TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+GRANT SELECT, INSERT ON deleted_users TO natter_api_user;
+GRANT DELETE ON spaces TO natter_api_user;
@@ -0,0 +1,3 @@
+REVOKE UPDATE ON oauth2_codes FROM natter_api_user;
+DROP INDEX IF EXISTS expired_oauth2_code_idx;
+ALTER TABLE oauth2_codes DROP COLUMN IF EXISTS family_id;
@@ -0,0 +1,4 @@
+ALTER TABLE oauth2_codes ADD COLUMN family_id VARCHAR(100) NULL;
+CREATE INDEX expired_oauth2_code_idx ON oauth2_codes(expiry);
+
+GRANT UPDATE ON oauth2_codes TO natter_api_user;
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS expired_token_idx;
+DROP TABLE IF EXISTS tokens;
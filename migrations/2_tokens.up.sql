@@ -0,0 +1,9 @@
+CREATE TABLE tokens (
+    token_id VARCHAR(100) PRIMARY KEY,
+    user_id VARCHAR(30) NOT NULL REFERENCES users(user_id),
+    expiry TIMESTAMPTZ NOT NULL,
+    attributes VARCHAR(4096) NOT NULL
+);
+CREATE INDEX expired_token_idx ON tokens(expiry);
+
+GRANT SELECT, INSERT, DELETE ON tokens TO natter_api_user;
@@ -0,0 +1,4 @@
+REVOKE UPDATE ON tokens FROM natter_api_user;
+DROP INDEX IF EXISTS token_user_idx;
+ALTER TABLE tokens DROP COLUMN IF EXISTS last_used;
+ALTER TABLE tokens DROP COLUMN IF EXISTS created;
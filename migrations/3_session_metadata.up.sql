@@ -0,0 +1,5 @@
+ALTER TABLE tokens ADD COLUMN created TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE tokens ADD COLUMN last_used TIMESTAMPTZ NULL;
+CREATE INDEX token_user_idx ON tokens(user_id);
+
+GRANT UPDATE ON tokens TO natter_api_user;
@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS expired_refresh_token_idx;
+DROP TABLE IF EXISTS refresh_tokens;
+DROP TABLE IF EXISTS oauth2_codes;
+DROP TABLE IF EXISTS oauth2_clients;
@@ -0,0 +1,31 @@
+CREATE TABLE oauth2_clients (
+    client_id VARCHAR(30) PRIMARY KEY,
+    client_secret_hash VARCHAR(100) NULL,
+    name VARCHAR(255) NOT NULL,
+    owner VARCHAR(30) NOT NULL REFERENCES users(user_id),
+    redirect_uris VARCHAR(2048) NOT NULL,
+    scope VARCHAR(255) NOT NULL
+);
+
+CREATE TABLE oauth2_codes (
+    code_hash VARCHAR(100) PRIMARY KEY,
+    client_id VARCHAR(30) NOT NULL REFERENCES oauth2_clients(client_id),
+    user_id VARCHAR(30) NOT NULL REFERENCES users(user_id),
+    redirect_uri VARCHAR(2048) NOT NULL,
+    scope VARCHAR(255) NOT NULL,
+    code_challenge VARCHAR(100) NULL,
+    expiry TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE refresh_tokens (
+    token_hash VARCHAR(100) PRIMARY KEY,
+    user_id VARCHAR(30) NOT NULL REFERENCES users(user_id),
+    client_id VARCHAR(30) NULL REFERENCES oauth2_clients(client_id),
+    scope VARCHAR(255) NULL,
+    expiry TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX expired_refresh_token_idx ON refresh_tokens(expiry);
+
+GRANT SELECT, INSERT ON oauth2_clients TO natter_api_user;
+GRANT SELECT, INSERT, DELETE ON oauth2_codes TO natter_api_user;
+GRANT SELECT, INSERT, DELETE ON refresh_tokens TO natter_api_user;
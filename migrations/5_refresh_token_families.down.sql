@@ -0,0 +1,6 @@
+REVOKE UPDATE ON refresh_tokens FROM natter_api_user;
+DROP INDEX IF EXISTS refresh_token_session_idx;
+DROP INDEX IF EXISTS refresh_token_family_idx;
+ALTER TABLE refresh_tokens DROP COLUMN IF EXISTS used;
+ALTER TABLE refresh_tokens DROP COLUMN IF EXISTS session_id;
+ALTER TABLE refresh_tokens DROP COLUMN IF EXISTS family_id;
@@ -0,0 +1,9 @@
+ALTER TABLE refresh_tokens ADD COLUMN family_id VARCHAR(100) NULL;
+UPDATE refresh_tokens SET family_id = token_hash;
+ALTER TABLE refresh_tokens ALTER COLUMN family_id SET NOT NULL;
+ALTER TABLE refresh_tokens ADD COLUMN session_id VARCHAR(100) NULL;
+ALTER TABLE refresh_tokens ADD COLUMN used BOOLEAN NOT NULL DEFAULT false;
+CREATE INDEX refresh_token_family_idx ON refresh_tokens(family_id);
+CREATE INDEX refresh_token_session_idx ON refresh_tokens(session_id);
+
+GRANT UPDATE ON refresh_tokens TO natter_api_user;
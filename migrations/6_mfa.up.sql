@@ -0,0 +1,18 @@
+CREATE TABLE user_mfa (
+    user_id VARCHAR(30) PRIMARY KEY REFERENCES users(user_id),
+    totp_secret VARCHAR(64) NOT NULL,
+    confirmed BOOLEAN NOT NULL DEFAULT false,
+    last_used_step BIGINT NULL,
+    created TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE recovery_codes (
+    user_id VARCHAR(30) NOT NULL REFERENCES users(user_id),
+    code_hash VARCHAR(100) NOT NULL,
+    PRIMARY KEY (user_id, code_hash)
+);
+
+ALTER TABLE refresh_tokens ADD COLUMN attributes VARCHAR(4096) NOT NULL DEFAULT '{}';
+
+GRANT SELECT, INSERT, UPDATE, DELETE ON user_mfa TO natter_api_user;
+GRANT SELECT, INSERT, DELETE ON recovery_codes TO natter_api_user;
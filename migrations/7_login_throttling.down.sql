@@ -0,0 +1,2 @@
+ALTER TABLE audit_log DROP COLUMN IF EXISTS event;
+DROP TABLE IF EXISTS login_failures;
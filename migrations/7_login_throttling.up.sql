@@ -0,0 +1,12 @@
+CREATE TABLE login_failures (
+    attempt_key VARCHAR(64) PRIMARY KEY,
+    failures INT NOT NULL DEFAULT 0,
+    lockouts INT NOT NULL DEFAULT 0,
+    last_failure TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    locked_until TIMESTAMPTZ NULL
+);
+CREATE INDEX login_failure_time_idx ON login_failures(last_failure);
+
+ALTER TABLE audit_log ADD COLUMN event VARCHAR(30) NULL;
+
+GRANT SELECT, INSERT, UPDATE, DELETE ON login_failures TO natter_api_user;
@@ -0,0 +1,2 @@
+REVOKE UPDATE ON users FROM natter_api_user;
+DROP TABLE IF EXISTS password_reset_tokens;
@@ -0,0 +1,10 @@
+CREATE TABLE password_reset_tokens (
+    token_hash VARCHAR(100) PRIMARY KEY,
+    user_id VARCHAR(30) NOT NULL REFERENCES users(user_id),
+    expiry TIMESTAMPTZ NOT NULL,
+    created TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX password_reset_user_idx ON password_reset_tokens(user_id);
+
+GRANT UPDATE ON users TO natter_api_user;
+GRANT SELECT, INSERT, DELETE ON password_reset_tokens TO natter_api_user;
@@ -0,0 +1,9 @@
+REVOKE DELETE ON oauth2_clients FROM natter_api_user;
+REVOKE DELETE ON permissions FROM natter_api_user;
+REVOKE DELETE ON users FROM natter_api_user;
+
+ALTER TABLE users DROP COLUMN IF EXISTS is_admin;
+ALTER TABLE users DROP COLUMN IF EXISTS created;
+ALTER TABLE users DROP COLUMN IF EXISTS avatar_url;
+ALTER TABLE users DROP COLUMN IF EXISTS bio;
+ALTER TABLE users DROP COLUMN IF EXISTS display_name;
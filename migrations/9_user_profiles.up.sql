@@ -0,0 +1,9 @@
+ALTER TABLE users ADD COLUMN display_name VARCHAR(100) NULL;
+ALTER TABLE users ADD COLUMN bio VARCHAR(1024) NULL;
+ALTER TABLE users ADD COLUMN avatar_url VARCHAR(2048) NULL;
+ALTER TABLE users ADD COLUMN created TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT false;
+
+GRANT DELETE ON users TO natter_api_user;
+GRANT DELETE ON permissions TO natter_api_user;
+GRANT DELETE ON oauth2_clients TO natter_api_user;
@@ -1,15 +1,25 @@
+use crate::credential_cache::CredentialCache;
 use crate::error::ApiError;
+use crate::lockout::LoginThrottle;
+use crate::notifier::Notifier;
+use crate::password::{PasswordHashing, PasswordPolicy};
+use crate::policy::PolicyEngine;
+use crate::tokens::{
+    refresh::RefreshTokenStore, Token, TokenStore, AUTH_TIME_ATTRIBUTE, MFA_ATTRIBUTE,
+    SCOPE_ATTRIBUTE,
+};
 use anyhow::anyhow;
 use axum::{
     async_trait,
     body::Body,
     extract::{
-        rejection::{JsonRejection, PathRejection, QueryRejection},
+        rejection::{FormRejection, JsonRejection, PathRejection, QueryRejection},
         FromRequest, RequestParts,
     },
     http::{header::HeaderValue, header::LOCATION, StatusCode},
     response::{IntoResponse, Response},
 };
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use governor::{clock::DefaultClock, state::direct::NotKeyed, state::InMemoryState, RateLimiter};
 use serde::{de::DeserializeOwned, Serialize};
 use sqlx::PgPool;
@@ -19,13 +29,117 @@ use std::sync::Arc;
 pub struct ApiContext {
     pub db: PgPool,
     pub limiter: Arc<RateLimiter<NotKeyed, InMemoryState, DefaultClock>>,
+    pub tokens: Arc<dyn TokenStore>,
+    pub token_expiry: Duration,
+    pub refresh_tokens: RefreshTokenStore,
+    pub refresh_token_expiry: Duration,
+    pub login_throttle: LoginThrottle,
+    pub notifier: Arc<dyn Notifier>,
+    pub password_reset_expiry: Duration,
+    pub password_policy: PasswordPolicy,
+    pub password_hashing: PasswordHashing,
+    pub credential_cache: Arc<CredentialCache>,
+    pub policy: Arc<dyn PolicyEngine>,
+    pub capability_expiry: Duration,
 }
 
-#[derive(Clone)]
+pub const SCOPES: [&str; 13] = [
+    "create_space",
+    "post_message",
+    "read_message",
+    "list_messages",
+    "delete_message",
+    "list_members",
+    "manage_members",
+    "manage_account",
+    "manage_mfa",
+    "list_sessions",
+    "manage_sessions",
+    "read_group",
+    "manage_groups",
+];
+
+#[derive(Clone, Default)]
 pub struct AuthContext {
     pub subject: Option<String>,
+    pub token: Option<String>,
+    pub scope: Option<Vec<String>>,
+    pub mfa: bool,
+    pub auth_time: Option<DateTime<Utc>>,
+    pub expiry: Option<DateTime<Utc>>,
+}
+
+impl AuthContext {
+    pub fn from_token(token_id: &str, token: Token) -> Self {
+        let scope = token
+            .attributes
+            .get(SCOPE_ATTRIBUTE)
+            .map(|scope| scope.split_whitespace().map(String::from).collect());
+        let mfa = token
+            .attributes
+            .get(MFA_ATTRIBUTE)
+            .is_some_and(|mfa| mfa == "true");
+        let auth_time = token
+            .attributes
+            .get(AUTH_TIME_ATTRIBUTE)
+            .and_then(|auth_time| auth_time.parse().ok())
+            .and_then(|auth_time| Utc.timestamp_opt(auth_time, 0).single());
+        AuthContext {
+            subject: Some(token.username),
+            token: Some(token_id.to_string()),
+            scope,
+            mfa,
+            auth_time,
+            expiry: Some(token.expiry),
+        }
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scope
+            .as_ref()
+            .is_none_or(|scopes| scopes.iter().any(|s| s == scope))
+    }
 }
 
+#[derive(Clone)]
+pub struct Scope(pub &'static str);
+
+#[derive(Clone)]
+pub struct Action(pub &'static str);
+
+#[derive(Clone, Default)]
+pub struct StepUp {
+    pub max_age: Option<Duration>,
+    pub mfa: bool,
+    // Only requires a second factor from users who have enrolled one.
+    pub mfa_if_enrolled: bool,
+}
+
+impl StepUp {
+    pub fn recent(max_age: Duration) -> Self {
+        StepUp {
+            max_age: Some(max_age),
+            ..StepUp::default()
+        }
+    }
+
+    pub fn recent_with_mfa(max_age: Duration) -> Self {
+        StepUp {
+            max_age: Some(max_age),
+            mfa_if_enrolled: true,
+            ..StepUp::default()
+        }
+    }
+
+    pub fn mfa() -> Self {
+        StepUp {
+            mfa: true,
+            ..StepUp::default()
+        }
+    }
+}
+
+#[allow(dead_code)]
 #[derive(Clone)]
 pub struct AuditContext {
     pub audit_id: i64,
@@ -36,6 +150,7 @@ pub struct Permission {
     pub read: bool,
     pub write: bool,
     pub delete: bool,
+    pub admin: bool,
 }
 
 impl Permission {
@@ -49,6 +164,9 @@ impl Permission {
         if self.delete && !user_permission.delete {
             return false;
         }
+        if self.admin && !user_permission.admin {
+            return false;
+        }
         true
     }
 }
@@ -59,6 +177,7 @@ impl From<&str> for Permission {
             read: s.contains('r'),
             write: s.contains('w'),
             delete: s.contains('d'),
+            admin: s.contains('a'),
         }
     }
 }
@@ -123,6 +242,37 @@ where
     }
 }
 
+pub struct Form<T>(pub T);
+
+#[async_trait]
+impl<T> FromRequest<Body> for Form<T>
+where
+    T: DeserializeOwned,
+{
+    type Rejection = ApiError;
+    async fn from_request(req: &mut RequestParts<Body>) -> Result<Self, Self::Rejection> {
+        match axum::Form::<T>::from_request(req).await {
+            Ok(value) => Ok(Self(value.0)),
+            Err(rejection) => {
+                let e = match rejection {
+                    FormRejection::InvalidFormContentType(_) => ApiError::BadRequest(
+                        "request missing the application/x-www-form-urlencoded content-type"
+                            .to_string(),
+                    ),
+                    FormRejection::FailedToDeserializeQueryString(err) => {
+                        ApiError::BadRequest(format!("invalid request form payload: {}", err))
+                    }
+                    err => ApiError::ServerError(anyhow!(
+                        "unknown error when parsing form payload: {}",
+                        err
+                    )),
+                };
+                Err(e)
+            }
+        }
+    }
+}
+
 pub struct Query<T>(pub T);
 
 #[async_trait]
@@ -0,0 +1,78 @@
+use crate::api::Permission;
+use crate::error::ApiError;
+use crate::tokens::{hash, random_id};
+use chrono::{DateTime, Utc};
+use sqlx::{query, PgExecutor, PgPool};
+
+pub const ACCESS_TOKEN_PARAM: &str = "access_token";
+// Not a valid user name, so no account can ever claim anonymous messages.
+pub const ANONYMOUS_AUTHOR: &str = "(anonymous)";
+
+pub struct Capability {
+    pub permission: Permission,
+    pub created_by: String,
+}
+
+pub fn is_valid_perms(perms: &str) -> bool {
+    !perms.is_empty() && perms.chars().all(|c| "rwd".contains(c))
+}
+
+pub fn capability_uri(uri: &str, token: &str) -> String {
+    format!("{}?{}={}", uri, ACCESS_TOKEN_PARAM, token)
+}
+
+pub async fn create<'e, E>(
+    executor: E,
+    space_id: i32,
+    perms: &str,
+    created_by: &str,
+    expiry: DateTime<Utc>,
+) -> Result<String, ApiError>
+where
+    E: PgExecutor<'e>,
+{
+    let token = random_id();
+    query!(
+        "INSERT INTO capabilities (token_hash, space_id, perms, created_by, expiry) VALUES ($1, $2, $3, $4, $5)",
+        hash(&token),
+        space_id,
+        perms,
+        created_by,
+        expiry
+    )
+    .execute(executor)
+    .await?;
+    Ok(token)
+}
+
+pub async fn lookup(
+    db: &PgPool,
+    space_id: i32,
+    token: &str,
+) -> Result<Option<Capability>, ApiError> {
+    let record = query!(
+        "SELECT perms, created_by FROM capabilities WHERE token_hash = $1 AND space_id = $2 AND expiry > now()",
+        hash(token),
+        space_id
+    )
+    .fetch_optional(db)
+    .await?;
+    Ok(record.map(|record| Capability {
+        permission: Permission::from(record.perms.as_str()),
+        created_by: record.created_by,
+    }))
+}
+
+pub async fn revoke_space(db: &PgPool, space_id: i32) -> Result<u64, ApiError> {
+    let result = query!("DELETE FROM capabilities WHERE space_id = $1", space_id)
+        .execute(db)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn delete_expired(db: &PgPool) -> Result<u64, ApiError> {
+    let result = query!("DELETE FROM capabilities WHERE expiry < now()")
+        .execute(db)
+        .await?;
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,119 @@
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+use std::{collections::HashMap, sync::Mutex};
+
+type CacheKey = [u8; 32];
+
+pub struct CredentialCache {
+    key: [u8; 32],
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<HashMap<CacheKey, DateTime<Utc>>>,
+}
+
+impl CredentialCache {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        CredentialCache {
+            key,
+            ttl,
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Entries are keyed on the stored password hash as well, so a password
+    // change on any instance makes them unreachable everywhere.
+    fn cache_key(&self, username: &str, pw_hash: &str, password: &str) -> CacheKey {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(&(username.len() as u64).to_be_bytes());
+        mac.update(username.as_bytes());
+        mac.update(&(pw_hash.len() as u64).to_be_bytes());
+        mac.update(pw_hash.as_bytes());
+        mac.update(password.as_bytes());
+        mac.finalize().into_bytes().into()
+    }
+
+    pub fn contains(&self, username: &str, pw_hash: &str, password: &str) -> bool {
+        if self.ttl <= Duration::zero() {
+            return false;
+        }
+        let key = self.cache_key(username, pw_hash, password);
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(&key) {
+            Some(expiry) if *expiry > Utc::now() => true,
+            Some(_) => {
+                entries.remove(&key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn insert(&self, username: &str, pw_hash: &str, password: &str) {
+        if self.ttl <= Duration::zero() {
+            return;
+        }
+        let key = self.cache_key(username, pw_hash, password);
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity {
+            let now = Utc::now();
+            entries.retain(|_, expiry| *expiry > now);
+            if entries.len() >= self.capacity {
+                entries.clear();
+            }
+        }
+        entries.insert(key, Utc::now() + self.ttl);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_credentials_are_found() {
+        let cache = CredentialCache::new(Duration::seconds(60), 10);
+        cache.insert("alice", "hash", "password");
+        assert!(cache.contains("alice", "hash", "password"));
+        assert!(!cache.contains("alice", "hash", "other"));
+        assert!(!cache.contains("bob", "hash", "password"));
+    }
+
+    #[test]
+    fn changing_the_stored_hash_misses_the_cache() {
+        let cache = CredentialCache::new(Duration::seconds(60), 10);
+        cache.insert("alice", "old-hash", "password");
+        assert!(!cache.contains("alice", "new-hash", "password"));
+    }
+
+    #[test]
+    fn entries_expire_after_the_ttl() {
+        let cache = CredentialCache::new(Duration::milliseconds(1), 10);
+        cache.insert("alice", "hash", "password");
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert!(!cache.contains("alice", "hash", "password"));
+        assert!(cache.entries.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn cache_does_not_grow_beyond_its_capacity() {
+        let cache = CredentialCache::new(Duration::seconds(60), 3);
+        for i in 0..10 {
+            cache.insert(&format!("user{}", i), "hash", "password");
+            assert!(cache.entries.lock().unwrap().len() <= 3);
+        }
+        assert!(cache.contains("user9", "hash", "password"));
+    }
+
+    #[test]
+    fn zero_ttl_disables_the_cache() {
+        let cache = CredentialCache::new(Duration::zero(), 10);
+        cache.insert("alice", "hash", "password");
+        assert!(!cache.contains("alice", "hash", "password"));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::password::PasswordViolation;
 use axum::response::{IntoResponse, Json, Response};
 use http::{
     header::{HeaderValue, RETRY_AFTER, WWW_AUTHENTICATE},
@@ -13,14 +14,28 @@ pub enum ApiError {
     BadRequest(String),
     #[error("{0}")]
     Conflict(String),
+    #[error("password does not meet the password policy")]
+    WeakPassword(Vec<PasswordViolation>),
     #[error("only support application/json content type")]
     OnlySupportJsonContentType,
     #[error("too many requests")]
     TooManyRequests,
+    #[error("too many failed login attempts, try again later")]
+    AccountLocked(i64),
     #[error("authentication required")]
     AuthenticationRequired,
+    #[error("second authentication factor required")]
+    MfaRequired,
+    #[error("recent authentication required, please re-authenticate")]
+    ReauthenticationRequired,
     #[error("access forbidden")]
     Forbidden,
+    #[error("access denied by policy: {0}")]
+    PolicyDenied(String),
+    #[error("token scope does not permit this operation")]
+    InsufficientScope(&'static str),
+    #[error("{0} is not supported by the configured token store")]
+    NotSupported(&'static str),
     #[error("internal server error")]
     ServerError(#[from] anyhow::Error),
     #[error("database error")]
@@ -33,10 +48,17 @@ impl IntoResponse for ApiError {
             ApiError::NotFound => StatusCode::NOT_FOUND,
             ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
             ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::WeakPassword(_) => StatusCode::BAD_REQUEST,
             ApiError::OnlySupportJsonContentType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
             ApiError::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::AccountLocked(_) => StatusCode::TOO_MANY_REQUESTS,
             ApiError::AuthenticationRequired => StatusCode::UNAUTHORIZED,
+            ApiError::MfaRequired => StatusCode::UNAUTHORIZED,
+            ApiError::ReauthenticationRequired => StatusCode::UNAUTHORIZED,
             ApiError::Forbidden => StatusCode::FORBIDDEN,
+            ApiError::PolicyDenied(_) => StatusCode::FORBIDDEN,
+            ApiError::InsufficientScope(_) => StatusCode::FORBIDDEN,
+            ApiError::NotSupported(_) => StatusCode::NOT_IMPLEMENTED,
             ApiError::DatabaseError(e) => {
                 dbg!(e);
                 StatusCode::INTERNAL_SERVER_ERROR
@@ -46,24 +68,35 @@ impl IntoResponse for ApiError {
                 StatusCode::INTERNAL_SERVER_ERROR
             }
         };
-        let mut response = (
-            status_code,
-            Json(json!({
-                "message": &self.to_string(),
-            })),
-        )
-            .into_response();
+        let mut body = json!({
+            "message": &self.to_string(),
+        });
+        if let ApiError::WeakPassword(violations) = &self {
+            body["reasons"] = json!(violations);
+        }
+        let mut response = (status_code, Json(body)).into_response();
         if let ApiError::TooManyRequests = &self {
             response
                 .headers_mut()
                 .insert(RETRY_AFTER, HeaderValue::from_static("2"));
         }
-        if let ApiError::AuthenticationRequired = &self {
+        if let ApiError::AccountLocked(retry_after) = &self {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(*retry_after));
+        }
+        if let ApiError::AuthenticationRequired | ApiError::ReauthenticationRequired = &self {
             response.headers_mut().insert(
                 WWW_AUTHENTICATE,
                 HeaderValue::from_static("Basic realm=\"/\", charset=\"UTF-8\""),
             );
         }
+        if let ApiError::InsufficientScope(scope) = &self {
+            let value = format!("Bearer error=\"insufficient_scope\", scope=\"{}\"", scope);
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                response.headers_mut().insert(WWW_AUTHENTICATE, value);
+            }
+        }
         response
     }
 }
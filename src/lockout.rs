@@ -0,0 +1,268 @@
+use crate::error::ApiError;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{query, query_scalar, PgPool};
+use std::net::IpAddr;
+
+const MAX_LOCKOUT_DOUBLINGS: i32 = 5;
+const LOCKOUT_STATUS: i32 = 429;
+
+#[derive(Clone, Debug)]
+pub struct LockoutPolicy {
+    pub max_user_failures: i32,
+    pub max_ip_failures: i32,
+    pub failure_window: Duration,
+    pub lockout: Duration,
+    pub failure_delay: std::time::Duration,
+}
+
+pub struct LoginAttempt<'a> {
+    pub username: &'a str,
+    pub ip: Option<IpAddr>,
+    pub method: &'a str,
+    pub path: &'a str,
+}
+
+impl LoginAttempt<'_> {
+    fn keys(&self) -> Vec<(String, &'static str)> {
+        let mut keys = vec![(format!("user:{}", self.username), "account_locked")];
+        if let Some(ip) = self.ip {
+            keys.push((format!("ip:{}", ip), "address_locked"));
+        }
+        keys
+    }
+}
+
+#[derive(Clone)]
+pub struct LoginThrottle {
+    db: PgPool,
+    policy: LockoutPolicy,
+}
+
+impl LoginThrottle {
+    pub fn new(db: PgPool, policy: LockoutPolicy) -> Self {
+        LoginThrottle { db, policy }
+    }
+
+    pub async fn check(&self, attempt: &LoginAttempt<'_>) -> Result<(), ApiError> {
+        let keys: Vec<String> = attempt.keys().into_iter().map(|(key, _)| key).collect();
+        let locked_until = query_scalar!(
+            "SELECT max(locked_until) FROM login_failures WHERE attempt_key = ANY($1) AND locked_until > now()",
+            &keys[..]
+        )
+        .fetch_one(&self.db)
+        .await?;
+        match locked_until {
+            Some(locked_until) => Err(locked_error(locked_until)),
+            None => Ok(()),
+        }
+    }
+
+    pub async fn record_failure(&self, attempt: &LoginAttempt<'_>) -> Result<(), ApiError> {
+        let window_start = Utc::now() - self.policy.failure_window;
+        let mut user_failures = 0;
+        let mut locked_until = None;
+        for (key, event) in attempt.keys() {
+            let record = query!(
+                "INSERT INTO login_failures (attempt_key, failures) VALUES ($1, 1) ON CONFLICT (attempt_key) DO UPDATE SET failures = CASE WHEN login_failures.last_failure < $2 THEN 1 ELSE login_failures.failures + 1 END, last_failure = now() RETURNING failures, lockouts",
+                key,
+                window_start
+            )
+            .fetch_one(&self.db)
+            .await?;
+            let max_failures = if key.starts_with("user:") {
+                user_failures = record.failures;
+                self.policy.max_user_failures
+            } else {
+                self.policy.max_ip_failures
+            };
+            if record.failures < max_failures {
+                continue;
+            }
+            let lockout =
+                self.policy.lockout * 2i32.pow(record.lockouts.min(MAX_LOCKOUT_DOUBLINGS) as u32);
+            let until = Utc::now() + lockout;
+            query!(
+                "UPDATE login_failures SET failures = 0, lockouts = lockouts + 1, locked_until = $2 WHERE attempt_key = $1",
+                key,
+                until
+            )
+            .execute(&self.db)
+            .await?;
+            tracing::warn!(
+                "{} locked out until {} after repeated login failures",
+                key,
+                until
+            );
+            self.audit_lockout(attempt, event).await?;
+            locked_until = locked_until.max(Some(until));
+        }
+        if let Some(locked_until) = locked_until {
+            return Err(locked_error(locked_until));
+        }
+        let delay_steps = user_failures.clamp(0, self.policy.max_user_failures) as u32;
+        tokio::time::sleep(self.policy.failure_delay * delay_steps).await;
+        Ok(())
+    }
+
+    pub async fn record_success(&self, attempt: &LoginAttempt<'_>) -> Result<(), ApiError> {
+        query!(
+            "DELETE FROM login_failures WHERE attempt_key = $1",
+            format!("user:{}", attempt.username)
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn audit_lockout(&self, attempt: &LoginAttempt<'_>, event: &str) -> Result<(), ApiError> {
+        query!(
+            "INSERT INTO audit_log(audit_id, method, path, user_id, status, event) VALUES (nextval('audit_id_seq'), $1, $2, $3, $4, $5)",
+            attempt.method,
+            attempt.path,
+            attempt.username,
+            LOCKOUT_STATUS,
+            event
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_expired(&self) -> Result<u64, ApiError> {
+        let result = query!(
+            "DELETE FROM login_failures WHERE last_failure < now() - interval '1 day' AND (locked_until IS NULL OR locked_until < now())"
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+fn locked_error(locked_until: DateTime<Utc>) -> ApiError {
+    ApiError::AccountLocked((locked_until - Utc::now()).num_seconds().max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middlewares::tests::{context, random_name};
+    use rand::Rng;
+    use std::net::Ipv4Addr;
+
+    async fn throttle(max_user_failures: i32, max_ip_failures: i32) -> LoginThrottle {
+        LoginThrottle::new(
+            context().await.db,
+            LockoutPolicy {
+                max_user_failures,
+                max_ip_failures,
+                failure_window: Duration::minutes(15),
+                lockout: Duration::minutes(15),
+                failure_delay: std::time::Duration::ZERO,
+            },
+        )
+    }
+
+    fn random_ip() -> IpAddr {
+        IpAddr::V4(Ipv4Addr::from(rand::thread_rng().gen::<[u8; 4]>()))
+    }
+
+    fn attempt<'a>(username: &'a str, ip: Option<IpAddr>) -> LoginAttempt<'a> {
+        LoginAttempt {
+            username,
+            ip,
+            method: "GET",
+            path: "/spaces",
+        }
+    }
+
+    async fn cleanup(throttle: &LoginThrottle, attempt: &LoginAttempt<'_>) {
+        let keys: Vec<String> = attempt.keys().into_iter().map(|(key, _)| key).collect();
+        query!(
+            "DELETE FROM login_failures WHERE attempt_key = ANY($1)",
+            &keys[..]
+        )
+        .execute(&throttle.db)
+        .await
+        .unwrap();
+    }
+
+    fn locked_for(result: Result<(), ApiError>) -> Option<i64> {
+        match result {
+            Ok(()) => None,
+            Err(ApiError::AccountLocked(seconds)) => Some(seconds),
+            Err(_) => panic!("unexpected error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn account_is_locked_at_the_failure_threshold() {
+        let throttle = throttle(3, 100).await;
+        let username = random_name();
+        let attempt = attempt(&username, None);
+        for _ in 0..2 {
+            assert_eq!(locked_for(throttle.record_failure(&attempt).await), None);
+            assert!(throttle.check(&attempt).await.is_ok());
+        }
+        assert!(locked_for(throttle.record_failure(&attempt).await).is_some());
+        assert!(locked_for(throttle.check(&attempt).await).is_some());
+        cleanup(&throttle, &attempt).await;
+    }
+
+    #[tokio::test]
+    async fn repeated_lockouts_double_in_length() {
+        let throttle = throttle(1, 100).await;
+        let username = random_name();
+        let attempt = attempt(&username, None);
+        for lockout_minutes in [15, 30, 60] {
+            let seconds = locked_for(throttle.record_failure(&attempt).await).unwrap();
+            assert!((lockout_minutes * 60 - 5..=lockout_minutes * 60).contains(&seconds));
+            // Lets the lockout lapse without forgetting that it happened.
+            query!(
+                "UPDATE login_failures SET locked_until = now() WHERE attempt_key = $1",
+                format!("user:{}", username)
+            )
+            .execute(&throttle.db)
+            .await
+            .unwrap();
+        }
+        cleanup(&throttle, &attempt).await;
+    }
+
+    #[tokio::test]
+    async fn address_and_account_are_locked_separately() {
+        let throttle = throttle(3, 2).await;
+        let ip = random_ip();
+        let first = random_name();
+        let second = random_name();
+        assert_eq!(
+            locked_for(throttle.record_failure(&attempt(&first, Some(ip))).await),
+            None
+        );
+        assert!(locked_for(throttle.record_failure(&attempt(&second, Some(ip))).await).is_some());
+        // Every account is locked from that address, but not from others.
+        let third = random_name();
+        assert!(locked_for(throttle.check(&attempt(&third, Some(ip))).await).is_some());
+        assert!(throttle
+            .check(&attempt(&first, Some(random_ip())))
+            .await
+            .is_ok());
+        cleanup(&throttle, &attempt(&first, Some(ip))).await;
+        cleanup(&throttle, &attempt(&second, Some(ip))).await;
+    }
+
+    #[tokio::test]
+    async fn success_resets_the_account_failures() {
+        let throttle = throttle(3, 100).await;
+        let username = random_name();
+        let attempt = attempt(&username, Some(random_ip()));
+        for _ in 0..2 {
+            throttle.record_failure(&attempt).await.unwrap();
+        }
+        throttle.record_success(&attempt).await.unwrap();
+        for _ in 0..2 {
+            assert_eq!(locked_for(throttle.record_failure(&attempt).await), None);
+        }
+        assert!(throttle.check(&attempt).await.is_ok());
+        cleanup(&throttle, &attempt).await;
+    }
+}
@@ -1,24 +1,79 @@
 use anyhow::Context;
 use axum::{middleware::from_fn, Extension, Router};
 use axum_server::tls_rustls::RustlsConfig;
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use credential_cache::CredentialCache;
 use governor::{Quota, RateLimiter};
 use http::header::{
     HeaderValue, CACHE_CONTROL, CONTENT_SECURITY_POLICY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
     X_XSS_PROTECTION,
 };
+use lockout::{LockoutPolicy, LoginThrottle};
 use nonzero_ext::nonzero;
-use sqlx::postgres::PgPoolOptions;
-use std::{net::SocketAddr, num::NonZeroU32, path::PathBuf, sync::Arc};
+use notifier::{FileNotifier, LogNotifier, Notifier};
+use password::{PasswordAlgorithm, PasswordHashing, PasswordPolicy};
+use policy::{PermitAll, PolicyEngine, RulePolicy};
+use sqlx::{postgres::PgPoolOptions, PgPool};
+use std::{net::SocketAddr, num::NonZeroU32, path::PathBuf, sync::Arc, time::Duration};
+use tokens::{
+    database::DatabaseTokenStore,
+    encrypted::EncryptedTokenStore,
+    hmac::{HmacKeys, HmacTokenStore},
+    hybrid::HybridTokenStore,
+    jwt::{JwtAlgorithm, JwtTokenStore},
+    oauth2::IntrospectionTokenStore,
+    refresh::RefreshTokenStore,
+    TokenStore,
+};
 use tower::ServiceBuilder;
 use tower_http::{set_header::SetResponseHeaderLayer, trace::TraceLayer};
 
 mod api;
+mod capability;
+mod credential_cache;
 mod error;
+mod lockout;
+mod mfa;
 mod middlewares;
+mod notifier;
+mod password;
+mod policy;
 mod routes;
+mod tokens;
 
 const DEFAULT_RATE_LIMIT: NonZeroU32 = nonzero!(2u32);
+const DEFAULT_TOKEN_EXPIRY_MINUTES: i64 = 10;
+const DEFAULT_REFRESH_TOKEN_EXPIRY_DAYS: i64 = 14;
+const DEFAULT_TOKEN_ISSUER: &str = "https://localhost:8000";
+const DEFAULT_LOGIN_MAX_USER_FAILURES: i32 = 5;
+const DEFAULT_LOGIN_MAX_IP_FAILURES: i32 = 20;
+const DEFAULT_LOGIN_FAILURE_WINDOW_MINUTES: i64 = 15;
+const DEFAULT_LOGIN_LOCKOUT_MINUTES: i64 = 15;
+const DEFAULT_LOGIN_FAILURE_DELAY_MS: u64 = 250;
+const DEFAULT_PASSWORD_RESET_EXPIRY_MINUTES: i64 = 30;
+const DEFAULT_PASSWORD_MIN_LENGTH: usize = 8;
+const DEFAULT_PASSWORD_MAX_LENGTH: usize = 64;
+const DEFAULT_SCRYPT_LOG_N: u8 = 15;
+const DEFAULT_SCRYPT_R: u32 = 8;
+const DEFAULT_SCRYPT_P: u32 = 1;
+const DEFAULT_ARGON2_MEMORY_KIB: u32 = 19456;
+const DEFAULT_ARGON2_ITERATIONS: u32 = 2;
+const DEFAULT_ARGON2_PARALLELISM: u32 = 1;
+const DEFAULT_PASSWORD_HASHING_WORKERS: usize = 4;
+const DEFAULT_PASSWORD_HASHING_QUEUE_DEPTH: usize = 64;
+const DEFAULT_CREDENTIAL_CACHE_TTL_SECONDS: i64 = 60;
+const MAX_CACHED_CREDENTIALS: usize = 10_000;
+const DEFAULT_CAPABILITY_EXPIRY_DAYS: i64 = 30;
+
+#[derive(Clone, Debug, ValueEnum)]
+enum TokenStoreKind {
+    Database,
+    Hmac,
+    Hybrid,
+    Jwt,
+    Encrypted,
+    Introspection,
+}
 
 #[derive(Debug, Parser)]
 struct Config {
@@ -26,6 +81,78 @@ struct Config {
     app_database_url: String,
     #[clap(long, env, default_value_t = DEFAULT_RATE_LIMIT)]
     rate_limit: NonZeroU32,
+    #[clap(long, env, default_value_t = DEFAULT_TOKEN_EXPIRY_MINUTES)]
+    token_expiry_minutes: i64,
+    #[clap(long, env, default_value_t = DEFAULT_REFRESH_TOKEN_EXPIRY_DAYS)]
+    refresh_token_expiry_days: i64,
+    #[clap(long, env, value_enum, default_value_t = TokenStoreKind::Database)]
+    token_store: TokenStoreKind,
+    #[clap(long, env, value_delimiter = ',')]
+    token_hmac_keys: Vec<String>,
+    #[clap(long, env, value_enum, default_value_t = JwtAlgorithm::Hs256)]
+    token_jwt_algorithm: JwtAlgorithm,
+    #[clap(long, env, value_delimiter = ',')]
+    token_jwt_keys: Vec<String>,
+    #[clap(long, env, default_value = DEFAULT_TOKEN_ISSUER)]
+    token_jwt_issuer: String,
+    #[clap(long, env, default_value = DEFAULT_TOKEN_ISSUER)]
+    token_jwt_audience: String,
+    #[clap(long, env, value_delimiter = ',')]
+    token_encryption_keys: Vec<String>,
+    #[clap(long, env)]
+    token_introspection_endpoint: Option<String>,
+    #[clap(long, env)]
+    token_revocation_endpoint: Option<String>,
+    #[clap(long, env)]
+    token_introspection_audience: Option<String>,
+    #[clap(long, env)]
+    oauth2_client_id: Option<String>,
+    #[clap(long, env)]
+    oauth2_client_secret: Option<String>,
+    #[clap(long, env, default_value_t = DEFAULT_LOGIN_MAX_USER_FAILURES)]
+    login_max_user_failures: i32,
+    #[clap(long, env, default_value_t = DEFAULT_LOGIN_MAX_IP_FAILURES)]
+    login_max_ip_failures: i32,
+    #[clap(long, env, default_value_t = DEFAULT_LOGIN_FAILURE_WINDOW_MINUTES)]
+    login_failure_window_minutes: i64,
+    #[clap(long, env, default_value_t = DEFAULT_LOGIN_LOCKOUT_MINUTES)]
+    login_lockout_minutes: i64,
+    #[clap(long, env, default_value_t = DEFAULT_LOGIN_FAILURE_DELAY_MS)]
+    login_failure_delay_ms: u64,
+    #[clap(long, env, default_value_t = DEFAULT_PASSWORD_RESET_EXPIRY_MINUTES)]
+    password_reset_expiry_minutes: i64,
+    #[clap(long, env)]
+    notification_file: Option<PathBuf>,
+    #[clap(long, env, default_value_t = DEFAULT_PASSWORD_MIN_LENGTH)]
+    password_min_length: usize,
+    #[clap(long, env, default_value_t = DEFAULT_PASSWORD_MAX_LENGTH)]
+    password_max_length: usize,
+    #[clap(long, env)]
+    password_blocklist_file: Option<PathBuf>,
+    #[clap(long, env, value_enum, default_value_t = PasswordAlgorithm::Scrypt)]
+    password_algorithm: PasswordAlgorithm,
+    #[clap(long, env, default_value_t = DEFAULT_SCRYPT_LOG_N)]
+    scrypt_log_n: u8,
+    #[clap(long, env, default_value_t = DEFAULT_SCRYPT_R)]
+    scrypt_r: u32,
+    #[clap(long, env, default_value_t = DEFAULT_SCRYPT_P)]
+    scrypt_p: u32,
+    #[clap(long, env, default_value_t = DEFAULT_ARGON2_MEMORY_KIB)]
+    argon2_memory_kib: u32,
+    #[clap(long, env, default_value_t = DEFAULT_ARGON2_ITERATIONS)]
+    argon2_iterations: u32,
+    #[clap(long, env, default_value_t = DEFAULT_ARGON2_PARALLELISM)]
+    argon2_parallelism: u32,
+    #[clap(long, env, default_value_t = DEFAULT_PASSWORD_HASHING_WORKERS)]
+    password_hashing_workers: usize,
+    #[clap(long, env, default_value_t = DEFAULT_PASSWORD_HASHING_QUEUE_DEPTH)]
+    password_hashing_queue_depth: usize,
+    #[clap(long, env, default_value_t = DEFAULT_CREDENTIAL_CACHE_TTL_SECONDS)]
+    credential_cache_ttl_seconds: i64,
+    #[clap(long, env)]
+    policy_file: Option<PathBuf>,
+    #[clap(long, env, default_value_t = DEFAULT_CAPABILITY_EXPIRY_DAYS)]
+    capability_expiry_days: i64,
 }
 
 #[tokio::main]
@@ -51,16 +178,161 @@ async fn main() -> anyhow::Result<()> {
 
     let limiter = Arc::new(RateLimiter::direct(Quota::per_second(DEFAULT_RATE_LIMIT)));
 
+    let tokens: Arc<dyn TokenStore> = match config.token_store {
+        TokenStoreKind::Database => {
+            let tokens = DatabaseTokenStore::new(db.clone());
+            spawn_expired_token_cleanup(tokens.clone());
+            Arc::new(tokens)
+        }
+        TokenStoreKind::Hmac => {
+            let keys =
+                tokens::parse_keys(&config.token_hmac_keys).context("invalid token HMAC keys")?;
+            Arc::new(HmacTokenStore::new(HmacKeys::new(keys)?))
+        }
+        TokenStoreKind::Hybrid => {
+            let keys =
+                tokens::parse_keys(&config.token_hmac_keys).context("invalid token HMAC keys")?;
+            let tokens = DatabaseTokenStore::new(db.clone());
+            spawn_expired_token_cleanup(tokens.clone());
+            let tokens = HybridTokenStore::new(db.clone(), tokens, HmacKeys::new(keys)?);
+            tokens
+                .spawn_revocation_listener()
+                .await
+                .context("unable to listen for token revocations")?;
+            Arc::new(tokens)
+        }
+        TokenStoreKind::Jwt => {
+            let keys =
+                tokens::parse_keys(&config.token_jwt_keys).context("invalid token JWT keys")?;
+            Arc::new(JwtTokenStore::new(
+                config.token_jwt_algorithm,
+                keys,
+                config.token_jwt_issuer,
+                config.token_jwt_audience,
+            )?)
+        }
+        TokenStoreKind::Encrypted => {
+            let keys = tokens::parse_keys(&config.token_encryption_keys)
+                .context("invalid token encryption keys")?;
+            Arc::new(EncryptedTokenStore::new(keys)?)
+        }
+        TokenStoreKind::Introspection => {
+            let introspection_endpoint = config
+                .token_introspection_endpoint
+                .context("token introspection endpoint must be configured")?;
+            let client_id = config
+                .oauth2_client_id
+                .context("OAuth2 client ID must be configured")?;
+            let client_secret = config
+                .oauth2_client_secret
+                .context("OAuth2 client secret must be configured")?;
+            Arc::new(IntrospectionTokenStore::new(
+                introspection_endpoint,
+                config.token_revocation_endpoint,
+                config.token_introspection_audience,
+                client_id,
+                client_secret,
+            )?)
+        }
+    };
+    let token_expiry = chrono::Duration::minutes(config.token_expiry_minutes);
+    let refresh_tokens = RefreshTokenStore::new(db.clone(), tokens.clone());
+    spawn_expired_refresh_token_cleanup(refresh_tokens.clone());
+    let refresh_token_expiry = chrono::Duration::days(config.refresh_token_expiry_days);
+    let login_throttle = LoginThrottle::new(
+        db.clone(),
+        LockoutPolicy {
+            max_user_failures: config.login_max_user_failures,
+            max_ip_failures: config.login_max_ip_failures,
+            failure_window: chrono::Duration::minutes(config.login_failure_window_minutes),
+            lockout: chrono::Duration::minutes(config.login_lockout_minutes),
+            failure_delay: Duration::from_millis(config.login_failure_delay_ms),
+        },
+    );
+    spawn_expired_login_failure_cleanup(login_throttle.clone());
+    let notifier: Arc<dyn Notifier> = match config.notification_file {
+        Some(path) => Arc::new(FileNotifier::new(path)),
+        None => Arc::new(LogNotifier),
+    };
+    let password_reset_expiry = chrono::Duration::minutes(config.password_reset_expiry_minutes);
+    let password_policy = PasswordPolicy::new(
+        config.password_min_length,
+        config.password_max_length,
+        config.password_blocklist_file.as_deref(),
+    )
+    .context("invalid password policy")?;
+    let password_hashing = PasswordHashing::new(
+        config.password_algorithm,
+        scrypt::Params::new(config.scrypt_log_n, config.scrypt_r, config.scrypt_p)
+            .map_err(|e| anyhow::anyhow!("invalid scrypt parameters: {}", e))?,
+        argon2::Params::new(
+            config.argon2_memory_kib,
+            config.argon2_iterations,
+            config.argon2_parallelism,
+            None,
+        )
+        .map_err(|e| anyhow::anyhow!("invalid Argon2 parameters: {}", e))?,
+        config.password_hashing_workers,
+        config.password_hashing_queue_depth,
+    );
+    spawn_password_hashing_metrics_reporter(password_hashing.clone());
+    let credential_cache = Arc::new(CredentialCache::new(
+        chrono::Duration::seconds(config.credential_cache_ttl_seconds),
+        MAX_CACHED_CREDENTIALS,
+    ));
+    let policy: Arc<dyn PolicyEngine> = match config.policy_file {
+        Some(path) => Arc::new(RulePolicy::from_file(&path)?),
+        None => Arc::new(PermitAll),
+    };
+    spawn_expired_capability_cleanup(db.clone());
+    spawn_expired_authorization_code_cleanup(db.clone());
+    let capability_expiry = chrono::Duration::days(config.capability_expiry_days);
+
     let app = Router::new()
         .nest(
             "/spaces",
-            routes::space::router().merge(routes::moderator::router()),
+            routes::space::router()
+                .merge(routes::moderator::router())
+                .merge(routes::member::router())
+                .merge(routes::capability::router()),
         )
-        .nest("/users", routes::user::router())
+        .nest(routes::session::SESSIONS_PATH, routes::session::router())
+        .nest("/.well-known", routes::well_known::router())
+        .nest(
+            "/users",
+            routes::user::router()
+                .merge(routes::mfa::router())
+                .merge(routes::password::router()),
+        )
+        .nest("/groups", routes::group::router())
+        .nest("/oauth2", routes::oauth2::router())
         .layer(
             ServiceBuilder::new()
-                .layer(TraceLayer::new_for_http())
-                .layer(Extension(api::ApiContext { db, limiter }))
+                .layer(TraceLayer::new_for_http().make_span_with(
+                    |req: &http::Request<axum::body::Body>| {
+                        tracing::debug_span!(
+                            "request",
+                            method = %req.method(),
+                            path = %req.uri().path(),
+                        )
+                    },
+                ))
+                .layer(Extension(api::ApiContext {
+                    db,
+                    limiter,
+                    tokens,
+                    token_expiry,
+                    refresh_tokens,
+                    refresh_token_expiry,
+                    login_throttle,
+                    notifier,
+                    password_reset_expiry,
+                    password_policy,
+                    password_hashing,
+                    credential_cache,
+                    policy,
+                    capability_expiry,
+                }))
                 .layer(SetResponseHeaderLayer::overriding(
                     X_CONTENT_TYPE_OPTIONS,
                     HeaderValue::from_static("nosniff"),
@@ -90,7 +362,87 @@ async fn main() -> anyhow::Result<()> {
     let addr = SocketAddr::from(([127, 0, 0, 1], 8000));
     tracing::debug!("listening on {}", addr);
     axum_server::bind_rustls(addr, tls_config)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .context("error running HTTP server")
 }
+
+fn spawn_expired_token_cleanup(tokens: DatabaseTokenStore) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(10 * 60));
+        loop {
+            interval.tick().await;
+            if let Err(e) = tokens.delete_expired().await {
+                tracing::warn!("failed to delete expired tokens: {}", e);
+            }
+        }
+    });
+}
+
+fn spawn_expired_refresh_token_cleanup(refresh_tokens: RefreshTokenStore) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(10 * 60));
+        loop {
+            interval.tick().await;
+            if let Err(e) = refresh_tokens.delete_expired().await {
+                tracing::warn!("failed to delete expired refresh tokens: {}", e);
+            }
+        }
+    });
+}
+
+fn spawn_expired_capability_cleanup(db: PgPool) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(10 * 60));
+        loop {
+            interval.tick().await;
+            if let Err(e) = capability::delete_expired(&db).await {
+                tracing::warn!("failed to delete expired capabilities: {}", e);
+            }
+        }
+    });
+}
+
+fn spawn_expired_authorization_code_cleanup(db: PgPool) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(10 * 60));
+        loop {
+            interval.tick().await;
+            if let Err(e) = routes::oauth2::delete_expired_codes(&db).await {
+                tracing::warn!("failed to delete expired authorization codes: {}", e);
+            }
+        }
+    });
+}
+
+fn spawn_expired_login_failure_cleanup(login_throttle: LoginThrottle) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(10 * 60));
+        loop {
+            interval.tick().await;
+            if let Err(e) = login_throttle.delete_expired().await {
+                tracing::warn!("failed to delete expired login failures: {}", e);
+            }
+        }
+    });
+}
+
+fn spawn_password_hashing_metrics_reporter(password_hashing: PasswordHashing) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            let metrics = password_hashing.metrics().take();
+            if metrics.completed > 0 || metrics.rejected > 0 {
+                tracing::info!(
+                    "password hashing: {} completed, {} rejected, mean wait {:?}, mean latency {:?}, max latency {:?}",
+                    metrics.completed,
+                    metrics.rejected,
+                    metrics.mean_wait,
+                    metrics.mean_latency,
+                    metrics.max_latency
+                );
+            }
+        }
+    });
+}
@@ -0,0 +1,203 @@
+use crate::error::ApiError;
+use crate::tokens::hash;
+use chrono::Utc;
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use sha1::Sha1;
+use sqlx::{query, PgPool};
+use subtle::ConstantTimeEq;
+
+const TIME_STEP_SECONDS: i64 = 30;
+const CODE_DIGITS: u32 = 6;
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    OsRng.fill_bytes(&mut secret);
+    BASE32_NOPAD.encode(&secret)
+}
+
+pub fn otpauth_uri(username: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/Natter:{}?secret={}&issuer=Natter&algorithm=SHA1&digits={}&period={}",
+        username, secret, CODE_DIGITS, TIME_STEP_SECONDS
+    )
+}
+
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut code = [0u8; 10];
+            OsRng.fill_bytes(&mut code);
+            BASE32_NOPAD.encode(&code).to_lowercase()
+        })
+        .collect()
+}
+
+fn hotp(secret: &[u8], counter: u64) -> Option<String> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).ok()?;
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes(digest[offset..offset + 4].try_into().ok()?) & 0x7fff_ffff;
+    Some(format!(
+        "{:0width$}",
+        binary % 10u32.pow(CODE_DIGITS),
+        width = CODE_DIGITS as usize
+    ))
+}
+
+fn verify_totp(secret: &str, code: &str, last_used_step: Option<i64>, time: i64) -> Option<i64> {
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let current_step = time / TIME_STEP_SECONDS;
+    (-ALLOWED_DRIFT_STEPS..=ALLOWED_DRIFT_STEPS)
+        .map(|drift| current_step + drift)
+        .filter(|step| last_used_step.is_none_or(|last_used_step| *step > last_used_step))
+        .find(|step| {
+            hotp(&secret, *step as u64)
+                .is_some_and(|expected| bool::from(expected.as_bytes().ct_eq(code.as_bytes())))
+        })
+}
+
+pub async fn check_totp(
+    db: &PgPool,
+    username: &str,
+    code: &str,
+    confirmed: bool,
+) -> Result<bool, ApiError> {
+    let record = query!(
+        "SELECT totp_secret, last_used_step FROM user_mfa WHERE user_id = $1 AND confirmed = $2",
+        username,
+        confirmed
+    )
+    .fetch_optional(db)
+    .await?;
+    let now = Utc::now().timestamp();
+    let step = match record.and_then(|r| verify_totp(&r.totp_secret, code, r.last_used_step, now)) {
+        Some(step) => step,
+        None => return Ok(false),
+    };
+    let result = query!(
+        "UPDATE user_mfa SET last_used_step = $2 WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)",
+        username,
+        step
+    )
+    .execute(db)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn is_enrolled(db: &PgPool, username: &str) -> Result<bool, ApiError> {
+    let result = query!(
+        "SELECT user_id FROM user_mfa WHERE user_id = $1 AND confirmed = true",
+        username
+    )
+    .fetch_optional(db)
+    .await?;
+    Ok(result.is_some())
+}
+
+pub async fn verify_second_factor(
+    db: &PgPool,
+    username: &str,
+    code: &str,
+) -> Result<bool, ApiError> {
+    if check_totp(db, username, code, true).await? {
+        return Ok(true);
+    }
+    let result = query!(
+        "DELETE FROM recovery_codes WHERE user_id = $1 AND code_hash = $2",
+        username,
+        hash(&code.to_lowercase())
+    )
+    .execute(db)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn replace_recovery_codes(db: &PgPool, username: &str) -> Result<Vec<String>, ApiError> {
+    let codes = generate_recovery_codes();
+    let mut transaction = db.begin().await?;
+    query!("DELETE FROM recovery_codes WHERE user_id = $1", username)
+        .execute(&mut transaction)
+        .await?;
+    for code in &codes {
+        query!(
+            "INSERT INTO recovery_codes (user_id, code_hash) VALUES ($1, $2)",
+            username,
+            hash(code)
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+    transaction.commit().await?;
+    Ok(codes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238 appendix B uses 8 digits; the last 6 are the 6-digit codes.
+    const RFC_6238_SECRET: &[u8] = b"12345678901234567890";
+    const RFC_6238_VECTORS: [(i64, &str); 6] = [
+        (59, "287082"),
+        (1111111109, "081804"),
+        (1111111111, "050471"),
+        (1234567890, "005924"),
+        (2000000000, "279037"),
+        (20000000000, "353130"),
+    ];
+
+    fn code_at(time: i64) -> String {
+        hotp(RFC_6238_SECRET, (time / TIME_STEP_SECONDS) as u64).unwrap()
+    }
+
+    #[test]
+    fn hotp_matches_rfc_6238_test_vectors() {
+        for (time, expected) in RFC_6238_VECTORS {
+            assert_eq!(code_at(time), expected, "T = {}", time);
+        }
+    }
+
+    #[test]
+    fn verify_totp_accepts_rfc_6238_test_vectors() {
+        let secret = BASE32_NOPAD.encode(RFC_6238_SECRET);
+        for (time, code) in RFC_6238_VECTORS {
+            assert_eq!(
+                verify_totp(&secret, code, None, time),
+                Some(time / TIME_STEP_SECONDS),
+                "T = {}",
+                time
+            );
+        }
+    }
+
+    #[test]
+    fn verify_totp_allows_one_step_of_drift() {
+        let secret = BASE32_NOPAD.encode(RFC_6238_SECRET);
+        let time = 1234567890;
+        let step = time / TIME_STEP_SECONDS;
+        for drift in -1..=1 {
+            let code = code_at(time + drift * TIME_STEP_SECONDS);
+            assert_eq!(verify_totp(&secret, &code, None, time), Some(step + drift));
+        }
+        for drift in [-2, 2] {
+            let code = code_at(time + drift * TIME_STEP_SECONDS);
+            assert_eq!(verify_totp(&secret, &code, None, time), None);
+        }
+    }
+
+    #[test]
+    fn verify_totp_rejects_reused_steps() {
+        let secret = BASE32_NOPAD.encode(RFC_6238_SECRET);
+        let time = 1234567890;
+        let step = time / TIME_STEP_SECONDS;
+        let code = code_at(time);
+        assert_eq!(verify_totp(&secret, &code, Some(step), time), None);
+        let previous = code_at(time - TIME_STEP_SECONDS);
+        assert_eq!(verify_totp(&secret, &previous, Some(step - 1), time), None);
+    }
+}
@@ -1,18 +1,23 @@
-use crate::api::{ApiContext, AuthContext, Permission};
+use crate::api::{Action, ApiContext, AuthContext, Permission, Scope, StepUp};
+use crate::capability;
 use crate::error::ApiError;
-use crate::routes::USER_REGEX;
+use crate::lockout::LoginAttempt;
+use crate::mfa;
+use crate::policy::{Decision, Environment, PolicyRequest, Resource, Subject};
+use crate::routes::{oauth2::OAUTH2_TOKEN_PATH, session::SESSIONS_PATH, USER_REGEX};
+use crate::tokens::{self, verify_csrf_token, Token, CSRF_TOKEN_HEADER, SESSION_COOKIE};
 use anyhow::anyhow;
 use axum::{
-    extract::{FromRequest, Path, RequestParts, TypedHeader},
-    headers::{authorization, Authorization, ContentType},
+    extract::{ConnectInfo, FromRequest, Path, Query, RequestParts, TypedHeader},
+    headers::{authorization, Authorization, ContentType, Cookie},
     http::{Method, Request},
     middleware::Next,
     response::Response,
     Extension,
 };
-use scrypt::password_hash::PasswordVerifier;
-use scrypt::{password_hash::PasswordHash, Scrypt};
-use sqlx::{query, query_scalar};
+use chrono::Utc;
+use sqlx::{query, query_scalar, PgPool};
+use std::net::SocketAddr;
 
 pub async fn accept_only_json_payload_in_post<B>(
     req: Request<B>,
@@ -24,10 +29,12 @@ where
     if req.method() != Method::POST {
         return Ok(next.run(req).await);
     }
+    let accepts_form = req.uri().path() == OAUTH2_TOKEN_PATH;
     let mut req_parts = RequestParts::<B>::new(req);
     match TypedHeader::<ContentType>::from_request(&mut req_parts).await {
         Ok(TypedHeader(content_type)) => {
-            if content_type != ContentType::json() {
+            let is_form = accepts_form && content_type == ContentType::form_url_encoded();
+            if content_type != ContentType::json() && !is_form {
                 return Err(ApiError::OnlySupportJsonContentType);
             }
         }
@@ -58,12 +65,32 @@ where
     Ok(next.run(req).await)
 }
 
-pub async fn authenticate<B>(req: Request<B>, next: Next<B>) -> Result<Response, ApiError>
+async fn read_token(ctx: &ApiContext, token_id: &str) -> Result<Option<Token>, ApiError> {
+    match ctx.tokens.read(token_id).await? {
+        Some(token)
+            if ctx.tokens.is_self_contained() && !tokens::is_current(&ctx.db, &token).await? =>
+        {
+            Ok(None)
+        }
+        token => Ok(token),
+    }
+}
+
+pub async fn authenticate<B>(mut req: Request<B>, next: Next<B>) -> Result<Response, ApiError>
 where
     B: Send,
 {
-    let mut auth_ctx = AuthContext { subject: None };
+    let mut auth_ctx = AuthContext::default();
+    if req.uri().path() == OAUTH2_TOKEN_PATH {
+        req.extensions_mut().insert(auth_ctx);
+        return Ok(next.run(req).await);
+    }
     let mut req_parts = RequestParts::<B>::new(req);
+    let ctx = req_parts
+        .extensions()
+        .get::<ApiContext>()
+        .cloned()
+        .ok_or_else(|| ApiError::ServerError(anyhow!("failed to fetch context")))?;
     if let Ok(TypedHeader(basic_auth)) =
         TypedHeader::<Authorization<authorization::Basic>>::from_request(&mut req_parts).await
     {
@@ -72,22 +99,63 @@ where
         if !USER_REGEX.is_match(username) {
             return Err(ApiError::BadRequest("invalid user name".to_string()));
         }
-        let ctx = req_parts
-            .extensions()
-            .get::<ApiContext>()
-            .ok_or_else(|| ApiError::ServerError(anyhow!("failed to fetch context")))?;
-        let result = query_scalar!("SELECT pw_hash FROM users WHERE user_id = $1", username)
+        let attempt = LoginAttempt {
+            username,
+            ip: req_parts
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip()),
+            method: req_parts.method().as_str(),
+            path: req_parts.uri().path(),
+        };
+        ctx.login_throttle.check(&attempt).await?;
+        let pw_hash = query_scalar!("SELECT pw_hash FROM users WHERE user_id = $1", username)
             .fetch_optional(&ctx.db)
             .await?;
-        if let Some(hash) = result {
-            if let Ok(parsed_hash) = PasswordHash::new(&hash) {
-                if Scrypt
-                    .verify_password(password.as_bytes(), &parsed_hash)
-                    .is_ok()
-                {
-                    auth_ctx = AuthContext {
-                        subject: Some(username.to_string()),
-                    };
+        let is_cached = pw_hash
+            .as_deref()
+            .is_some_and(|pw_hash| ctx.credential_cache.contains(username, pw_hash, password));
+        if is_cached || verify_credentials(&ctx, username, pw_hash.as_deref(), password).await? {
+            if !is_cached {
+                ctx.login_throttle.record_success(&attempt).await?;
+                if let Some(pw_hash) = &pw_hash {
+                    ctx.credential_cache.insert(username, pw_hash, password);
+                }
+            }
+            // A password alone is not enough for an account with a second factor:
+            // it may only be exchanged for a session, which checks the OTP.
+            let is_create_session =
+                req_parts.method() == Method::POST && req_parts.uri().path() == SESSIONS_PATH;
+            if !is_create_session && mfa::is_enrolled(&ctx.db, username).await? {
+                return Err(ApiError::MfaRequired);
+            }
+            auth_ctx = AuthContext {
+                subject: Some(username.to_string()),
+                auth_time: Some(Utc::now()),
+                ..AuthContext::default()
+            };
+        } else {
+            ctx.login_throttle.record_failure(&attempt).await?;
+        }
+    } else if let Ok(TypedHeader(bearer_auth)) =
+        TypedHeader::<Authorization<authorization::Bearer>>::from_request(&mut req_parts).await
+    {
+        if let Some(token) = read_token(&ctx, bearer_auth.token()).await? {
+            auth_ctx = AuthContext::from_token(bearer_auth.token(), token);
+        }
+    } else if let Ok(TypedHeader(cookie)) =
+        TypedHeader::<Cookie>::from_request(&mut req_parts).await
+    {
+        if let Some(token_id) = cookie.get(SESSION_COOKIE) {
+            let is_csrf_valid = req_parts.method().is_safe()
+                || req_parts
+                    .headers()
+                    .get(CSRF_TOKEN_HEADER)
+                    .and_then(|value| value.to_str().ok())
+                    .is_some_and(|csrf_token| verify_csrf_token(token_id, csrf_token));
+            if is_csrf_valid {
+                if let Some(token) = read_token(&ctx, token_id).await? {
+                    auth_ctx = AuthContext::from_token(token_id, token);
                 }
             }
         }
@@ -99,6 +167,30 @@ where
     Ok(next.run(req).await)
 }
 
+async fn verify_credentials(
+    ctx: &ApiContext,
+    username: &str,
+    pw_hash: Option<&str>,
+    password: &str,
+) -> Result<bool, ApiError> {
+    let hash = match pw_hash {
+        Some(hash) if ctx.password_hashing.verify(hash, password).await? => hash,
+        _ => return Ok(false),
+    };
+    if ctx.password_hashing.needs_rehash(hash) {
+        let new_hash = ctx.password_hashing.hash(password).await?;
+        query!(
+            "UPDATE users SET pw_hash = $3 WHERE user_id = $1 AND pw_hash = $2",
+            username,
+            hash,
+            new_hash
+        )
+        .execute(&ctx.db)
+        .await?;
+    }
+    Ok(true)
+}
+
 pub async fn audit_request<B>(req: Request<B>, next: Next<B>) -> Result<Response, ApiError>
 where
     B: Send,
@@ -175,6 +267,27 @@ struct RequirePermissionPath {
     space_id: i32,
 }
 
+pub async fn load_permission(
+    db: &PgPool,
+    space_id: i32,
+    user_id: &str,
+) -> Result<Permission, ApiError> {
+    let perms = query_scalar!(
+        r#"WITH RECURSIVE user_groups(group_id) AS (
+            SELECT group_id FROM group_members WHERE user_id = $2
+            UNION
+            SELECT group_members.group_id FROM group_members JOIN user_groups ON group_members.member_group_id = user_groups.group_id
+        )
+        SELECT role_permissions.perms AS "perms!" FROM permissions JOIN role_permissions ON permissions.role = role_permissions.role
+        WHERE space_id = $1 AND (user_id = $2 OR group_id IN (SELECT group_id FROM user_groups))"#,
+        space_id,
+        user_id
+    )
+    .fetch_all(db)
+    .await?;
+    Ok(Permission::from(perms.concat().as_str()))
+}
+
 pub async fn require_permission<B>(req: Request<B>, next: Next<B>) -> Result<Response, ApiError>
 where
     B: Send,
@@ -197,14 +310,7 @@ where
     let ctx = Extension::<ApiContext>::from_request(&mut req_parts)
         .await
         .map_err(|rejection| ApiError::ServerError(rejection.into()))?;
-    let user_permission = query_scalar!(
-        "SELECT perms FROM permissions WHERE space_id = $1 AND user_id = $2",
-        space_id,
-        user_id
-    )
-    .fetch_optional(&ctx.db)
-    .await?
-    .map_or(Permission::default(), |s| Permission::from(s.as_str()));
+    let user_permission = load_permission(&ctx.db, space_id, user_id).await?;
     if !permission_required.is_allowed(&user_permission) {
         return Err(ApiError::Forbidden);
     }
@@ -213,3 +319,395 @@ where
         .expect("body should not be extracted");
     Ok(next.run(req).await)
 }
+
+#[derive(serde::Deserialize)]
+struct CapabilityQuery {
+    access_token: Option<String>,
+}
+
+pub async fn require_capability<B>(req: Request<B>, next: Next<B>) -> Result<Response, ApiError>
+where
+    B: Send,
+{
+    let mut req_parts = RequestParts::<B>::new(req);
+    let Query(params) = Query::<CapabilityQuery>::from_request(&mut req_parts)
+        .await
+        .map_err(|rejection| ApiError::BadRequest(rejection.to_string()))?;
+    let access_token = match params.access_token {
+        Some(access_token) => access_token,
+        None => {
+            let req = req_parts
+                .try_into_request()
+                .expect("body should not be extracted");
+            return require_permission(req, next).await;
+        }
+    };
+    let path_params = Path::<RequirePermissionPath>::from_request(&mut req_parts)
+        .await
+        .map_err(|rejection| ApiError::ServerError(rejection.into()))?;
+    let space_id = path_params.space_id;
+    let Extension(permission_required) = Extension::<Permission>::from_request(&mut req_parts)
+        .await
+        .map_err(|rejection| ApiError::ServerError(rejection.into()))?;
+    let ctx = Extension::<ApiContext>::from_request(&mut req_parts)
+        .await
+        .map_err(|rejection| ApiError::ServerError(rejection.into()))?;
+    let capability = capability::lookup(&ctx.db, space_id, &access_token)
+        .await?
+        .ok_or(ApiError::AuthenticationRequired)?;
+    // A capability never grants more than its creator still holds, so taking
+    // away their role also disables the links they handed out.
+    let creator_permission = load_permission(&ctx.db, space_id, &capability.created_by).await?;
+    if !permission_required.is_allowed(&capability.permission)
+        || !permission_required.is_allowed(&creator_permission)
+    {
+        return Err(ApiError::Forbidden);
+    }
+    let req = req_parts
+        .try_into_request()
+        .expect("body should not be extracted");
+    Ok(next.run(req).await)
+}
+
+#[derive(Default, serde::Deserialize)]
+struct PolicyPath {
+    space_id: Option<i32>,
+    msg_id: Option<i32>,
+}
+
+pub async fn enforce_policy<B>(req: Request<B>, next: Next<B>) -> Result<Response, ApiError>
+where
+    B: Send,
+{
+    let mut req_parts = RequestParts::<B>::new(req);
+    let path_params = Option::<Path<PolicyPath>>::from_request(&mut req_parts)
+        .await
+        .ok()
+        .flatten()
+        .map_or_else(PolicyPath::default, |Path(params)| params);
+    let auth_ctx = Extension::<AuthContext>::from_request(&mut req_parts)
+        .await
+        .map_err(|rejection| ApiError::ServerError(rejection.into()))?;
+    let Extension(Action(action)) = Extension::<Action>::from_request(&mut req_parts)
+        .await
+        .map_err(|rejection| ApiError::ServerError(rejection.into()))?;
+    let ctx = Extension::<ApiContext>::from_request(&mut req_parts)
+        .await
+        .map_err(|rejection| ApiError::ServerError(rejection.into()))?;
+    let mut resource = Resource {
+        space_id: path_params.space_id,
+        msg_id: path_params.msg_id,
+        ..Resource::default()
+    };
+    if let Some(space_id) = resource.space_id {
+        resource.space_owner =
+            query_scalar!("SELECT owner FROM spaces WHERE space_id = $1", space_id)
+                .fetch_optional(&ctx.db)
+                .await?;
+        if let Some(msg_id) = resource.msg_id {
+            resource.msg_author = query_scalar!(
+                "SELECT author FROM messages WHERE space_id = $1 AND msg_id = $2",
+                space_id,
+                msg_id
+            )
+            .fetch_optional(&ctx.db)
+            .await?;
+        }
+    }
+    let request = PolicyRequest {
+        subject: Subject {
+            user_id: auth_ctx.subject.clone(),
+            mfa: auth_ctx.mfa,
+        },
+        resource,
+        action,
+        environment: Environment {
+            time: Utc::now(),
+            ip: req_parts
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip()),
+        },
+    };
+    if let Decision::Deny(reason) = ctx.policy.evaluate(&request).await? {
+        tracing::info!(
+            "policy denied {} on {}: {}",
+            request.subject.user_id.as_deref().unwrap_or("anonymous"),
+            action,
+            reason
+        );
+        return Err(ApiError::PolicyDenied(reason));
+    }
+    let req = req_parts
+        .try_into_request()
+        .expect("body should not be extracted");
+    Ok(next.run(req).await)
+}
+
+pub async fn require_step_up<B>(req: Request<B>, next: Next<B>) -> Result<Response, ApiError>
+where
+    B: Send,
+{
+    let mut req_parts = RequestParts::<B>::new(req);
+    let auth_ctx = Extension::<AuthContext>::from_request(&mut req_parts)
+        .await
+        .map_err(|rejection| ApiError::ServerError(rejection.into()))?;
+    let subject = auth_ctx
+        .subject
+        .as_deref()
+        .ok_or(ApiError::AuthenticationRequired)?;
+    let Extension(step_up) = Extension::<StepUp>::from_request(&mut req_parts)
+        .await
+        .map_err(|rejection| ApiError::ServerError(rejection.into()))?;
+    if step_up.mfa && !auth_ctx.mfa {
+        return Err(ApiError::MfaRequired);
+    }
+    if step_up.mfa_if_enrolled && !auth_ctx.mfa {
+        let Extension(ctx) = Extension::<ApiContext>::from_request(&mut req_parts)
+            .await
+            .map_err(|rejection| ApiError::ServerError(rejection.into()))?;
+        if mfa::is_enrolled(&ctx.db, subject).await? {
+            return Err(ApiError::MfaRequired);
+        }
+    }
+    if let Some(max_age) = step_up.max_age {
+        let is_recent = auth_ctx
+            .auth_time
+            .is_some_and(|auth_time| Utc::now() - auth_time <= max_age);
+        if !is_recent {
+            return Err(ApiError::ReauthenticationRequired);
+        }
+    }
+    let req = req_parts
+        .try_into_request()
+        .expect("body should not be extracted");
+    Ok(next.run(req).await)
+}
+
+pub async fn require_scope<B>(req: Request<B>, next: Next<B>) -> Result<Response, ApiError>
+where
+    B: Send,
+{
+    let mut req_parts = RequestParts::<B>::new(req);
+    let auth_ctx = Extension::<AuthContext>::from_request(&mut req_parts)
+        .await
+        .map_err(|rejection| ApiError::ServerError(rejection.into()))?;
+    let Extension(Scope(scope_required)) =
+        Extension::<Scope>::from_request(&mut req_parts)
+            .await
+            .map_err(|rejection| ApiError::ServerError(rejection.into()))?;
+    if !auth_ctx.has_scope(scope_required) {
+        return Err(ApiError::InsufficientScope(scope_required));
+    }
+    let req = req_parts
+        .try_into_request()
+        .expect("body should not be extracted");
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::credential_cache::CredentialCache;
+    use crate::lockout::{LockoutPolicy, LoginThrottle};
+    use crate::notifier::LogNotifier;
+    use crate::password::{PasswordAlgorithm, PasswordHashing, PasswordPolicy};
+    use crate::policy::PermitAll;
+    use crate::tokens::{database::DatabaseTokenStore, refresh::RefreshTokenStore};
+    use axum::{
+        body::Body,
+        middleware::from_fn,
+        routing::{get, post},
+        Router,
+    };
+    use chrono::Duration;
+    use governor::{Quota, RateLimiter};
+    use http::{header::AUTHORIZATION, StatusCode};
+    use nonzero_ext::nonzero;
+    use rand::{distributions::Alphanumeric, Rng};
+    use sqlx::postgres::PgPoolOptions;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    pub(crate) const PASSWORD: &str = "correct-horse-battery";
+
+    /// Builds a context against the database named by `DATABASE_URL`, which the
+    /// query macros already require to be migrated.
+    pub(crate) async fn context() -> ApiContext {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set to run tests");
+        let db = PgPoolOptions::new()
+            .max_connections(5)
+            .connect(&url)
+            .await
+            .expect("unable to connect to test database");
+        let tokens = Arc::new(DatabaseTokenStore::new(db.clone()));
+        ApiContext {
+            limiter: Arc::new(RateLimiter::direct(Quota::per_second(nonzero!(1000u32)))),
+            refresh_tokens: RefreshTokenStore::new(db.clone(), tokens.clone()),
+            tokens,
+            token_expiry: Duration::minutes(10),
+            refresh_token_expiry: Duration::days(14),
+            login_throttle: LoginThrottle::new(
+                db.clone(),
+                LockoutPolicy {
+                    max_user_failures: 3,
+                    max_ip_failures: 20,
+                    failure_window: Duration::minutes(15),
+                    lockout: Duration::minutes(15),
+                    failure_delay: std::time::Duration::ZERO,
+                },
+            ),
+            notifier: Arc::new(LogNotifier),
+            password_reset_expiry: Duration::minutes(30),
+            password_policy: PasswordPolicy::new(8, 64, None).unwrap(),
+            password_hashing: PasswordHashing::new(
+                PasswordAlgorithm::Scrypt,
+                scrypt::Params::new(4, 8, 1).unwrap(),
+                argon2::Params::default(),
+                2,
+                16,
+            ),
+            credential_cache: Arc::new(CredentialCache::new(Duration::seconds(60), 100)),
+            policy: Arc::new(PermitAll),
+            capability_expiry: Duration::days(30),
+            db,
+        }
+    }
+
+    pub(crate) fn random_name() -> String {
+        let suffix: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect();
+        format!("t{}", suffix)
+    }
+
+    pub(crate) async fn create_user(ctx: &ApiContext) -> String {
+        let username = random_name();
+        let pw_hash = ctx.password_hashing.hash(PASSWORD).await.unwrap();
+        query!(
+            "INSERT INTO users (user_id, pw_hash) VALUES ($1, $2)",
+            username,
+            pw_hash
+        )
+        .execute(&ctx.db)
+        .await
+        .unwrap();
+        username
+    }
+
+    /// Removes a user created by [`create_user`] along with anything the tests
+    /// attached to it.
+    pub(crate) async fn delete_user(ctx: &ApiContext, username: &str) {
+        let statements = [
+            "DELETE FROM tokens WHERE user_id = $1",
+            "DELETE FROM refresh_tokens WHERE user_id = $1",
+            "DELETE FROM oauth2_codes WHERE user_id = $1 OR client_id IN (SELECT client_id FROM oauth2_clients WHERE owner = $1)",
+            "DELETE FROM oauth2_clients WHERE owner = $1",
+            "DELETE FROM recovery_codes WHERE user_id = $1",
+            "DELETE FROM user_mfa WHERE user_id = $1",
+            "DELETE FROM password_reset_tokens WHERE user_id = $1",
+            "DELETE FROM login_failures WHERE attempt_key = 'user:' || $1",
+            "DELETE FROM group_members WHERE user_id = $1 OR group_id IN (SELECT group_id FROM groups WHERE owner = $1) OR member_group_id IN (SELECT group_id FROM groups WHERE owner = $1)",
+            "DELETE FROM permissions WHERE user_id = $1 OR group_id IN (SELECT group_id FROM groups WHERE owner = $1) OR space_id IN (SELECT space_id FROM spaces WHERE owner = $1)",
+            "DELETE FROM groups WHERE owner = $1",
+            "DELETE FROM capabilities WHERE created_by = $1 OR space_id IN (SELECT space_id FROM spaces WHERE owner = $1)",
+            "DELETE FROM messages WHERE space_id IN (SELECT space_id FROM spaces WHERE owner = $1)",
+            "DELETE FROM spaces WHERE owner = $1",
+            "DELETE FROM users WHERE user_id = $1",
+        ];
+        for statement in statements {
+            sqlx::query(statement)
+                .bind(username)
+                .execute(&ctx.db)
+                .await
+                .unwrap();
+        }
+    }
+
+    async fn subject(Extension(auth_ctx): Extension<AuthContext>) -> String {
+        auth_ctx.subject.unwrap_or_default()
+    }
+
+    fn app(ctx: &ApiContext) -> Router {
+        Router::new()
+            .route(SESSIONS_PATH, post(subject))
+            .route("/spaces", get(subject))
+            .layer(from_fn(authenticate))
+            .layer(Extension(ctx.clone()))
+    }
+
+    fn request(method: Method, uri: &str, authorization: String) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(AUTHORIZATION, authorization)
+            .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 0))))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    pub(crate) fn basic_request(method: Method, uri: &str, username: &str) -> Request<Body> {
+        let credentials = base64::encode(format!("{}:{}", username, PASSWORD));
+        request(method, uri, format!("Basic {}", credentials))
+    }
+
+    pub(crate) fn bearer_request(method: Method, uri: &str, token: &str) -> Request<Body> {
+        request(method, uri, format!("Bearer {}", token))
+    }
+
+    pub(crate) fn with_json(request: Request<Body>, body: serde_json::Value) -> Request<Body> {
+        let (mut parts, _) = request.into_parts();
+        parts.headers.insert(
+            http::header::CONTENT_TYPE,
+            http::HeaderValue::from_static("application/json"),
+        );
+        Request::from_parts(parts, Body::from(body.to_string()))
+    }
+
+    #[tokio::test]
+    async fn basic_auth_authenticates_users_without_mfa() {
+        let ctx = context().await;
+        let username = create_user(&ctx).await;
+        let response = app(&ctx)
+            .oneshot(basic_request(Method::GET, "/spaces", &username))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, username.as_bytes());
+        delete_user(&ctx, &username).await;
+    }
+
+    #[tokio::test]
+    async fn basic_auth_is_rejected_for_mfa_enrolled_users() {
+        let ctx = context().await;
+        let username = create_user(&ctx).await;
+        query!(
+            "INSERT INTO user_mfa (user_id, totp_secret, confirmed) VALUES ($1, $2, true)",
+            username,
+            mfa::generate_secret()
+        )
+        .execute(&ctx.db)
+        .await
+        .unwrap();
+        let response = app(&ctx)
+            .oneshot(basic_request(Method::GET, "/spaces", &username))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        // Cached credentials must not bypass the check either.
+        let response = app(&ctx)
+            .oneshot(basic_request(Method::GET, "/spaces", &username))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app(&ctx)
+            .oneshot(basic_request(Method::POST, SESSIONS_PATH, &username))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        delete_user(&ctx, &username).await;
+    }
+}
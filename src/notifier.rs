@@ -0,0 +1,61 @@
+use crate::error::ApiError;
+use anyhow::Context;
+use axum::async_trait;
+use chrono::Utc;
+use serde_json::json;
+use std::path::PathBuf;
+use tokio::{fs::OpenOptions, io::AsyncWriteExt, sync::Mutex};
+
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn send(&self, username: &str, subject: &str, message: &str) -> Result<(), ApiError>;
+}
+
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn send(&self, username: &str, subject: &str, message: &str) -> Result<(), ApiError> {
+        tracing::info!("notification to {}: {}: {}", username, subject, message);
+        Ok(())
+    }
+}
+
+pub struct FileNotifier {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileNotifier {
+    pub fn new(path: PathBuf) -> Self {
+        FileNotifier {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for FileNotifier {
+    async fn send(&self, username: &str, subject: &str, message: &str) -> Result<(), ApiError> {
+        let mut line = json!({
+            "time": Utc::now(),
+            "to": username,
+            "subject": subject,
+            "message": message,
+        })
+        .to_string();
+        line.push('\n');
+        let _guard = self.lock.lock().await;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .context("failed to open notification file")?;
+        file.write_all(line.as_bytes())
+            .await
+            .context("failed to write notification")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,444 @@
+use crate::error::ApiError;
+use anyhow::Context;
+use argon2::Argon2;
+use clap::ValueEnum;
+use data_encoding::HEXUPPER;
+use scrypt::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, SaltString},
+    Scrypt,
+};
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use std::{
+    collections::HashSet,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::Semaphore;
+use unicode_normalization::UnicodeNormalization;
+
+// Shorter user names occur in too many ordinary passwords to be worth rejecting.
+const MIN_CHECKED_USERNAME_LENGTH: usize = 4;
+
+const COMMON_PASSWORDS: [&str; 20] = [
+    "password",
+    "password1",
+    "password123",
+    "12345678",
+    "123456789",
+    "1234567890",
+    "qwertyuiop",
+    "qwerty123",
+    "iloveyou",
+    "11111111",
+    "00000000",
+    "abc12345",
+    "abcd1234",
+    "sunshine",
+    "princess",
+    "football",
+    "baseball",
+    "welcome1",
+    "letmein1",
+    "trustno1",
+];
+
+pub fn normalize_password(password: &str) -> String {
+    password.nfkc().collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PasswordAlgorithm {
+    Scrypt,
+    Argon2id,
+}
+
+#[derive(Default)]
+pub struct HashingMetrics {
+    completed: AtomicU64,
+    rejected: AtomicU64,
+    total_wait_micros: AtomicU64,
+    total_latency_micros: AtomicU64,
+    max_latency_micros: AtomicU64,
+}
+
+#[derive(Debug)]
+pub struct HashingMetricsSnapshot {
+    pub completed: u64,
+    pub rejected: u64,
+    pub mean_wait: Duration,
+    pub mean_latency: Duration,
+    pub max_latency: Duration,
+}
+
+impl HashingMetrics {
+    fn record(&self, wait: Duration, latency: Duration) {
+        let latency_micros = latency.as_micros() as u64;
+        self.completed.fetch_add(1, Ordering::Relaxed);
+        self.total_wait_micros
+            .fetch_add(wait.as_micros() as u64, Ordering::Relaxed);
+        self.total_latency_micros
+            .fetch_add(latency_micros, Ordering::Relaxed);
+        self.max_latency_micros
+            .fetch_max(latency_micros, Ordering::Relaxed);
+    }
+
+    pub fn take(&self) -> HashingMetricsSnapshot {
+        let completed = self.completed.swap(0, Ordering::Relaxed);
+        let mean = |total: u64| Duration::from_micros(total.checked_div(completed).unwrap_or(0));
+        HashingMetricsSnapshot {
+            completed,
+            rejected: self.rejected.swap(0, Ordering::Relaxed),
+            mean_wait: mean(self.total_wait_micros.swap(0, Ordering::Relaxed)),
+            mean_latency: mean(self.total_latency_micros.swap(0, Ordering::Relaxed)),
+            max_latency: Duration::from_micros(self.max_latency_micros.swap(0, Ordering::Relaxed)),
+        }
+    }
+}
+
+struct HashingPool {
+    workers: Arc<Semaphore>,
+    pending: AtomicUsize,
+    capacity: usize,
+    metrics: HashingMetrics,
+}
+
+struct PendingGuard<'a>(&'a AtomicUsize);
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[derive(Clone)]
+pub struct PasswordHashing {
+    algorithm: PasswordAlgorithm,
+    scrypt_params: scrypt::Params,
+    argon2_params: argon2::Params,
+    pool: Arc<HashingPool>,
+}
+
+impl PasswordHashing {
+    pub fn new(
+        algorithm: PasswordAlgorithm,
+        scrypt_params: scrypt::Params,
+        argon2_params: argon2::Params,
+        workers: usize,
+        queue_depth: usize,
+    ) -> Self {
+        PasswordHashing {
+            algorithm,
+            scrypt_params,
+            argon2_params,
+            pool: Arc::new(HashingPool {
+                workers: Arc::new(Semaphore::new(workers)),
+                pending: AtomicUsize::new(0),
+                capacity: workers + queue_depth,
+                metrics: HashingMetrics::default(),
+            }),
+        }
+    }
+
+    pub fn metrics(&self) -> &HashingMetrics {
+        &self.pool.metrics
+    }
+
+    async fn run<T, F>(&self, f: F) -> Result<T, ApiError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&PasswordHashing) -> T + Send + 'static,
+    {
+        let pool = &self.pool;
+        if pool.pending.fetch_add(1, Ordering::SeqCst) >= pool.capacity {
+            pool.pending.fetch_sub(1, Ordering::SeqCst);
+            pool.metrics.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(ApiError::TooManyRequests);
+        }
+        let _pending = PendingGuard(&pool.pending);
+        let queued = Instant::now();
+        let permit = pool
+            .workers
+            .clone()
+            .acquire_owned()
+            .await
+            .context("password hashing pool is closed")?;
+        let wait = queued.elapsed();
+        let hashing = self.clone();
+        let started = Instant::now();
+        let result = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            f(&hashing)
+        })
+        .await
+        .context("password hashing task failed")?;
+        let latency = started.elapsed();
+        pool.metrics.record(wait, latency);
+        tracing::debug!(
+            "password hashing took {:?} after waiting {:?}",
+            latency,
+            wait
+        );
+        Ok(result)
+    }
+
+    pub async fn hash(&self, password: &str) -> Result<String, ApiError> {
+        let password = password.to_string();
+        Ok(self
+            .run(move |hashing| hashing.hash_blocking(&password))
+            .await??)
+    }
+
+    pub async fn verify(&self, hash: &str, password: &str) -> Result<bool, ApiError> {
+        let hash = hash.to_string();
+        let password = password.to_string();
+        self.run(move |_| verify_blocking(&hash, &password)).await
+    }
+
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(
+            argon2::Algorithm::Argon2id,
+            argon2::Version::V0x13,
+            self.argon2_params.clone(),
+        )
+    }
+
+    fn hash_blocking(&self, password: &str) -> anyhow::Result<String> {
+        let password = normalize_password(password);
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = match self.algorithm {
+            PasswordAlgorithm::Scrypt => Scrypt.hash_password_customized(
+                password.as_bytes(),
+                None,
+                None,
+                self.scrypt_params,
+                &salt,
+            ),
+            PasswordAlgorithm::Argon2id => self.argon2().hash_password(password.as_bytes(), &salt),
+        }
+        .context("failed to hash password")?;
+        Ok(hash.to_string())
+    }
+
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        let parsed_hash = match PasswordHash::new(hash) {
+            Ok(parsed_hash) => parsed_hash,
+            Err(_) => return true,
+        };
+        match self.algorithm {
+            PasswordAlgorithm::Scrypt => {
+                parsed_hash.algorithm != scrypt::ALG_ID
+                    || scrypt::Params::try_from(&parsed_hash).map_or(true, |params| {
+                        params.log_n() != self.scrypt_params.log_n()
+                            || params.r() != self.scrypt_params.r()
+                            || params.p() != self.scrypt_params.p()
+                    })
+            }
+            PasswordAlgorithm::Argon2id => {
+                parsed_hash.algorithm != argon2::ARGON2ID_IDENT
+                    || parsed_hash.version != Some(argon2::Version::V0x13.into())
+                    || argon2::Params::try_from(&parsed_hash).map_or(true, |params| {
+                        params.m_cost() != self.argon2_params.m_cost()
+                            || params.t_cost() != self.argon2_params.t_cost()
+                            || params.p_cost() != self.argon2_params.p_cost()
+                    })
+            }
+        }
+    }
+}
+
+fn verify_blocking(hash: &str, password: &str) -> bool {
+    let normalized = normalize_password(password);
+    verify_with(hash, normalized.as_bytes())
+        || (normalized != password && verify_with(hash, password.as_bytes()))
+}
+
+fn verify_with(hash: &str, password: &[u8]) -> bool {
+    if hash.starts_with("$2") {
+        return bcrypt::verify(password, hash).unwrap_or(false);
+    }
+    PasswordHash::new(hash).is_ok_and(|parsed_hash| {
+        parsed_hash
+            .verify_password(&[&Scrypt, &Argon2::default()], password)
+            .is_ok()
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum PasswordViolation {
+    TooShort { min_length: usize },
+    TooLong { max_length: usize },
+    ContainsUsername,
+    Breached,
+}
+
+#[derive(Clone)]
+pub struct PasswordPolicy {
+    min_length: usize,
+    max_length: usize,
+    common_passwords: Arc<HashSet<String>>,
+    breached_hashes: Arc<HashSet<String>>,
+}
+
+impl PasswordPolicy {
+    pub fn new(
+        min_length: usize,
+        max_length: usize,
+        blocklist: Option<&Path>,
+    ) -> anyhow::Result<Self> {
+        let mut common_passwords: HashSet<String> =
+            COMMON_PASSWORDS.iter().map(|s| s.to_string()).collect();
+        let mut breached_hashes = HashSet::new();
+        if let Some(path) = blocklist {
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("unable to read password blocklist {}", path.display()))?;
+            for line in content.lines().map(str::trim).filter(|l| !l.is_empty()) {
+                let sha1 = line.split(':').next().unwrap_or_default();
+                if sha1.len() == 40 && sha1.chars().all(|c| c.is_ascii_hexdigit()) {
+                    breached_hashes.insert(sha1.to_ascii_uppercase());
+                } else {
+                    common_passwords.insert(normalize_password(line).to_lowercase());
+                }
+            }
+        }
+        Ok(PasswordPolicy {
+            min_length,
+            max_length,
+            common_passwords: Arc::new(common_passwords),
+            breached_hashes: Arc::new(breached_hashes),
+        })
+    }
+
+    pub fn check(&self, username: &str, raw_password: &str) -> Result<(), ApiError> {
+        let password = normalize_password(raw_password);
+        let mut violations = Vec::new();
+        let length = password.chars().count();
+        if length < self.min_length {
+            violations.push(PasswordViolation::TooShort {
+                min_length: self.min_length,
+            });
+        }
+        if length > self.max_length {
+            violations.push(PasswordViolation::TooLong {
+                max_length: self.max_length,
+            });
+        }
+        let lowercase = password.to_lowercase();
+        if username.chars().count() >= MIN_CHECKED_USERNAME_LENGTH
+            && lowercase.contains(&username.to_lowercase())
+        {
+            violations.push(PasswordViolation::ContainsUsername);
+        }
+        // Breach corpora hash passwords exactly as they were leaked, so the raw
+        // input is checked as well as the form it is stored in.
+        if self.common_passwords.contains(&lowercase)
+            || self.is_breached(raw_password)
+            || self.is_breached(&password)
+        {
+            violations.push(PasswordViolation::Breached);
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(ApiError::WeakPassword(violations))
+        }
+    }
+
+    fn is_breached(&self, password: &str) -> bool {
+        if self.breached_hashes.is_empty() {
+            return false;
+        }
+        let digest = Sha1::digest(password.as_bytes());
+        self.breached_hashes.contains(&HEXUPPER.encode(&digest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn violations(
+        policy: &PasswordPolicy,
+        username: &str,
+        password: &str,
+    ) -> Vec<PasswordViolation> {
+        match policy.check(username, password) {
+            Ok(()) => Vec::new(),
+            Err(ApiError::WeakPassword(violations)) => violations,
+            Err(_) => panic!("unexpected error"),
+        }
+    }
+
+    fn sha1(password: &str) -> String {
+        HEXUPPER.encode(&Sha1::digest(password.as_bytes()))
+    }
+
+    #[test]
+    fn length_is_counted_in_characters() {
+        let policy = PasswordPolicy::new(8, 12, None).unwrap();
+        assert!(violations(&policy, "alice", "héllo-wörld").is_empty());
+        assert_eq!(
+            violations(&policy, "alice", "short"),
+            vec![PasswordViolation::TooShort { min_length: 8 }]
+        );
+        assert_eq!(
+            violations(&policy, "alice", "far-too-long-password"),
+            vec![PasswordViolation::TooLong { max_length: 12 }]
+        );
+    }
+
+    #[test]
+    fn password_may_not_contain_the_username() {
+        let policy = PasswordPolicy::new(8, 64, None).unwrap();
+        assert_eq!(
+            violations(&policy, "alice", "my-ALICE-secret"),
+            vec![PasswordViolation::ContainsUsername]
+        );
+        // Very short user names are not checked.
+        assert!(violations(&policy, "bob", "bobsled-races").is_empty());
+    }
+
+    #[test]
+    fn common_passwords_are_rejected() {
+        let policy = PasswordPolicy::new(8, 64, None).unwrap();
+        assert_eq!(
+            violations(&policy, "alice", "PassWord123"),
+            vec![PasswordViolation::Breached]
+        );
+    }
+
+    #[test]
+    fn breached_passwords_are_rejected_in_raw_and_normalized_form() {
+        // The fullwidth letters normalize to "Correct-Horse".
+        let raw = "\u{ff23}orrect-\u{ff28}orse";
+        let normalized = "Correct-Horse";
+        let path = std::env::temp_dir().join(format!("natter-blocklist-{}", std::process::id()));
+        std::fs::write(&path, format!("{}:3\n", sha1(raw))).unwrap();
+        let policy = PasswordPolicy::new(8, 64, Some(&path)).unwrap();
+        assert_eq!(
+            violations(&policy, "alice", raw),
+            vec![PasswordViolation::Breached]
+        );
+        std::fs::write(
+            &path,
+            format!("{}:3\nsome-common-phrase\n", sha1(normalized)),
+        )
+        .unwrap();
+        let policy = PasswordPolicy::new(8, 64, Some(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            violations(&policy, "alice", raw),
+            vec![PasswordViolation::Breached]
+        );
+        assert_eq!(
+            violations(&policy, "alice", "Some-Common-Phrase"),
+            vec![PasswordViolation::Breached]
+        );
+        assert!(violations(&policy, "alice", "battery-staple").is_empty());
+    }
+}
@@ -0,0 +1,354 @@
+use crate::error::ApiError;
+use anyhow::{anyhow, Context};
+use axum::async_trait;
+use chrono::{DateTime, Datelike, Duration, NaiveTime, Utc, Weekday};
+use serde::{Deserialize, Deserializer};
+use std::{net::IpAddr, path::Path, str::FromStr};
+
+pub struct Subject {
+    pub user_id: Option<String>,
+    pub mfa: bool,
+}
+
+#[derive(Default)]
+pub struct Resource {
+    pub space_id: Option<i32>,
+    pub space_owner: Option<String>,
+    pub msg_id: Option<i32>,
+    pub msg_author: Option<String>,
+}
+
+pub struct Environment {
+    pub time: DateTime<Utc>,
+    pub ip: Option<IpAddr>,
+}
+
+pub struct PolicyRequest<'a> {
+    pub subject: Subject,
+    pub resource: Resource,
+    pub action: &'a str,
+    pub environment: Environment,
+}
+
+pub enum Decision {
+    Permit,
+    Deny(String),
+}
+
+#[async_trait]
+pub trait PolicyEngine: Send + Sync {
+    async fn evaluate(&self, request: &PolicyRequest<'_>) -> Result<Decision, ApiError>;
+}
+
+pub struct PermitAll;
+
+#[async_trait]
+impl PolicyEngine for PermitAll {
+    async fn evaluate(&self, _request: &PolicyRequest<'_>) -> Result<Decision, ApiError> {
+        Ok(Decision::Permit)
+    }
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Effect {
+    #[default]
+    Permit,
+    Deny,
+}
+
+#[derive(Deserialize)]
+struct Rule {
+    #[serde(default)]
+    description: String,
+    effect: Effect,
+    #[serde(default)]
+    actions: Vec<String>,
+    #[serde(default)]
+    condition: Condition,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Condition {
+    #[default]
+    Always,
+    All {
+        conditions: Vec<Condition>,
+    },
+    Any {
+        conditions: Vec<Condition>,
+    },
+    Not {
+        condition: Box<Condition>,
+    },
+    TimeBetween {
+        #[serde(deserialize_with = "deserialize_time")]
+        start: NaiveTime,
+        #[serde(deserialize_with = "deserialize_time")]
+        end: NaiveTime,
+        #[serde(default)]
+        days: Vec<Weekday>,
+        #[serde(default)]
+        utc_offset_minutes: i64,
+    },
+    IpIn {
+        networks: Vec<Network>,
+    },
+    Authenticated,
+    SubjectIsOwner,
+    SubjectIsAuthor,
+    Mfa,
+}
+
+fn deserialize_time<'de, D>(deserializer: D) -> Result<NaiveTime, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&s, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(&s, "%H:%M"))
+        .map_err(serde::de::Error::custom)
+}
+
+impl Condition {
+    fn matches(&self, request: &PolicyRequest<'_>) -> bool {
+        match self {
+            Condition::Always => true,
+            Condition::All { conditions } => conditions.iter().all(|c| c.matches(request)),
+            Condition::Any { conditions } => conditions.iter().any(|c| c.matches(request)),
+            Condition::Not { condition } => !condition.matches(request),
+            Condition::TimeBetween {
+                start,
+                end,
+                days,
+                utc_offset_minutes,
+            } => {
+                let local = request.environment.time + Duration::minutes(*utc_offset_minutes);
+                let time = local.time();
+                let in_window = if start <= end {
+                    *start <= time && time < *end
+                } else {
+                    *start <= time || time < *end
+                };
+                in_window && (days.is_empty() || days.contains(&local.weekday()))
+            }
+            Condition::IpIn { networks } => request
+                .environment
+                .ip
+                .is_some_and(|ip| networks.iter().any(|network| network.contains(ip))),
+            Condition::Authenticated => request.subject.user_id.is_some(),
+            Condition::SubjectIsOwner => {
+                request.subject.user_id.is_some()
+                    && request.resource.space_owner == request.subject.user_id
+            }
+            Condition::SubjectIsAuthor => {
+                request.subject.user_id.is_some()
+                    && request.resource.msg_author == request.subject.user_id
+            }
+            Condition::Mfa => request.subject.mfa,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
+struct Network {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl Network {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl TryFrom<String> for Network {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (IpAddr::from_str(addr)?, Some(prefix.parse::<u32>()?)),
+            None => (IpAddr::from_str(&s)?, None),
+        };
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max_prefix);
+        if prefix > max_prefix {
+            return Err(anyhow!("invalid network prefix in {}", s));
+        }
+        Ok(Network { addr, prefix })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RulePolicy {
+    #[serde(default)]
+    default: Effect,
+    rules: Vec<Rule>,
+}
+
+impl RulePolicy {
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read policy file {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("invalid policy file {}", path.display()))
+    }
+}
+
+#[async_trait]
+impl PolicyEngine for RulePolicy {
+    async fn evaluate(&self, request: &PolicyRequest<'_>) -> Result<Decision, ApiError> {
+        let rule = self.rules.iter().enumerate().find(|(_, rule)| {
+            (rule.actions.is_empty() || rule.actions.iter().any(|a| a == request.action))
+                && rule.condition.matches(request)
+        });
+        let (effect, description) = match rule {
+            Some((_, rule)) if !rule.description.is_empty() => {
+                (rule.effect, rule.description.clone())
+            }
+            Some((index, rule)) => (rule.effect, format!("rule {}", index + 1)),
+            None => (self.default, "default policy".to_string()),
+        };
+        Ok(match effect {
+            Effect::Permit => Decision::Permit,
+            Effect::Deny => Decision::Deny(description),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    fn network(s: &str) -> Network {
+        Network::try_from(s.to_string()).unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        IpAddr::from_str(s).unwrap()
+    }
+
+    fn request<'a>(action: &'a str, user_id: Option<&str>, ip: &str) -> PolicyRequest<'a> {
+        PolicyRequest {
+            subject: Subject {
+                user_id: user_id.map(String::from),
+                mfa: false,
+            },
+            resource: Resource::default(),
+            action,
+            environment: Environment {
+                time: Utc.timestamp_opt(1_704_110_400, 0).unwrap(),
+                ip: Some(self::ip(ip)),
+            },
+        }
+    }
+
+    async fn evaluate(policy: &RulePolicy, request: &PolicyRequest<'_>) -> Option<String> {
+        match policy.evaluate(request).await.unwrap() {
+            Decision::Permit => None,
+            Decision::Deny(reason) => Some(reason),
+        }
+    }
+
+    #[test]
+    fn zero_prefix_matches_every_address_of_its_family() {
+        let any_v4 = network("10.0.0.0/0");
+        assert!(any_v4.contains(ip("192.168.1.1")));
+        assert!(any_v4.contains(ip("255.255.255.255")));
+        assert!(!any_v4.contains(ip("::1")));
+        assert!(network("::/0").contains(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn full_prefix_matches_a_single_address() {
+        let host = network("192.168.1.10/32");
+        assert!(host.contains(ip("192.168.1.10")));
+        assert!(!host.contains(ip("192.168.1.11")));
+        assert!(network("192.168.1.10").contains(ip("192.168.1.10")));
+        let host = network("2001:db8::1/128");
+        assert!(host.contains(ip("2001:db8::1")));
+        assert!(!host.contains(ip("2001:db8::2")));
+    }
+
+    #[test]
+    fn prefix_masks_the_host_bits() {
+        let subnet = network("10.1.0.0/16");
+        assert!(subnet.contains(ip("10.1.255.1")));
+        assert!(!subnet.contains(ip("10.2.0.1")));
+    }
+
+    #[test]
+    fn address_families_do_not_match_each_other() {
+        assert!(!network("0.0.0.0/0").contains(ip("2001:db8::1")));
+        assert!(!network("::/0").contains(ip("10.0.0.1")));
+        // IPv4-mapped IPv6 addresses are compared as IPv4.
+        assert!(network("10.0.0.0/8").contains(ip("::ffff:10.0.0.1")));
+    }
+
+    #[test]
+    fn prefix_longer_than_the_address_is_rejected() {
+        assert!(Network::try_from("10.0.0.0/33".to_string()).is_err());
+        assert!(Network::try_from("::/129".to_string()).is_err());
+    }
+
+    #[tokio::test]
+    async fn first_matching_rule_wins() {
+        let policy: RulePolicy = serde_json::from_value(json!({
+            "default": "deny",
+            "rules": [
+                {"description": "office", "effect": "permit",
+                 "condition": {"type": "ip_in", "networks": ["10.0.0.0/8"]}},
+                {"description": "no deletes", "effect": "deny", "actions": ["delete_message"]},
+                {"effect": "permit", "condition": {"type": "authenticated"}}
+            ]
+        }))
+        .unwrap();
+        // An earlier permit takes precedence over a later deny.
+        let office = request("delete_message", Some("alice"), "10.0.0.1");
+        assert_eq!(evaluate(&policy, &office).await, None);
+        let remote = request("delete_message", Some("alice"), "192.0.2.1");
+        assert_eq!(
+            evaluate(&policy, &remote).await.as_deref(),
+            Some("no deletes")
+        );
+        let read = request("read_message", Some("alice"), "192.0.2.1");
+        assert_eq!(evaluate(&policy, &read).await, None);
+        let anonymous = request("read_message", None, "192.0.2.1");
+        assert_eq!(
+            evaluate(&policy, &anonymous).await.as_deref(),
+            Some("default policy")
+        );
+    }
+
+    #[tokio::test]
+    async fn deny_rules_take_precedence_over_later_permits() {
+        let policy: RulePolicy = serde_json::from_value(json!({
+            "rules": [
+                {"effect": "deny", "condition": {"type": "not", "condition": {"type": "authenticated"}}},
+                {"effect": "permit"}
+            ]
+        }))
+        .unwrap();
+        let anonymous = request("create_group", None, "10.0.0.1");
+        assert_eq!(
+            evaluate(&policy, &anonymous).await.as_deref(),
+            Some("rule 1")
+        );
+        let alice = request("create_group", Some("alice"), "10.0.0.1");
+        assert_eq!(evaluate(&policy, &alice).await, None);
+    }
+}
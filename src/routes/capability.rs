@@ -0,0 +1,98 @@
+use crate::api::{Action, ApiContext, AuthContext, CreatedJson, Json, Path, Permission, Scope};
+use crate::capability;
+use crate::error::ApiError;
+use crate::middlewares::{enforce_policy, load_permission, require_permission, require_scope};
+use axum::{
+    extract::OriginalUri, handler::Handler, middleware::from_fn, routing::post, Extension, Router,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+const ADMIN: Permission = Permission {
+    read: false,
+    write: false,
+    delete: false,
+    admin: true,
+};
+
+pub fn router() -> Router {
+    let create_capability = create_capability
+        .layer(from_fn(enforce_policy))
+        .layer(Extension(Action("create_capability")))
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_members")))
+        .layer(from_fn(require_permission))
+        .layer(Extension(ADMIN));
+    let revoke_capabilities = revoke_capabilities
+        .layer(from_fn(enforce_policy))
+        .layer(Extension(Action("revoke_capabilities")))
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_members")))
+        .layer(from_fn(require_permission))
+        .layer(Extension(ADMIN));
+    Router::new().route(
+        "/:space_id/capabilities",
+        post(create_capability).delete(revoke_capabilities),
+    )
+}
+
+#[derive(Deserialize)]
+struct CreateCapabilityPayload {
+    permissions: String,
+    expiry: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct CapabilityBody {
+    uri: String,
+    permissions: String,
+    expiry: DateTime<Utc>,
+}
+
+async fn create_capability(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    OriginalUri(uri): OriginalUri,
+    Path(space_id): Path<i32>,
+    Json(payload): Json<CreateCapabilityPayload>,
+) -> Result<CreatedJson<CapabilityBody>, ApiError> {
+    if !capability::is_valid_perms(&payload.permissions) {
+        return Err(ApiError::BadRequest("invalid permissions".to_string()));
+    }
+    let max_expiry = Utc::now() + ctx.capability_expiry;
+    let expiry = payload.expiry.unwrap_or(max_expiry);
+    if expiry <= Utc::now() || expiry > max_expiry {
+        return Err(ApiError::BadRequest("invalid expiry".to_string()));
+    }
+    let granter = auth_ctx.subject.as_deref().unwrap_or_default();
+    let granter_permission = load_permission(&ctx.db, space_id, granter).await?;
+    if !Permission::from(payload.permissions.as_str()).is_allowed(&granter_permission) {
+        return Err(ApiError::Forbidden);
+    }
+    let token =
+        capability::create(&ctx.db, space_id, &payload.permissions, granter, expiry).await?;
+    let space_uri = uri.to_string();
+    let space_uri = space_uri.trim_end_matches("/capabilities");
+    let uri = capability::capability_uri(space_uri, &token);
+    Ok(CreatedJson(
+        uri.clone(),
+        CapabilityBody {
+            uri,
+            permissions: payload.permissions,
+            expiry,
+        },
+    ))
+}
+
+#[derive(Serialize)]
+struct RevokeCapabilitiesBody {
+    revoked: u64,
+}
+
+async fn revoke_capabilities(
+    ctx: Extension<ApiContext>,
+    Path(space_id): Path<i32>,
+) -> Result<Json<RevokeCapabilitiesBody>, ApiError> {
+    let revoked = capability::revoke_space(&ctx.db, space_id).await?;
+    Ok(Json(RevokeCapabilitiesBody { revoked }))
+}
@@ -0,0 +1,484 @@
+use crate::api::{Action, ApiContext, AuthContext, CreatedJson, Json, Path, Scope};
+use crate::error::ApiError;
+use crate::middlewares::{enforce_policy, require_authentication, require_scope};
+use crate::routes::{check_self_or_admin, map_constraint_error, USER_REGEX};
+use axum::{
+    extract::OriginalUri,
+    handler::Handler,
+    middleware::from_fn,
+    routing::{delete, get, post},
+    Extension, Router,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_scalar};
+
+pub fn router() -> Router {
+    let create_group = create_group
+        .layer(from_fn(enforce_policy))
+        .layer(Extension(Action("create_group")))
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_groups")))
+        .layer(from_fn(require_authentication));
+    let read_group = read_group
+        .layer(from_fn(enforce_policy))
+        .layer(Extension(Action("read_group")))
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("read_group")))
+        .layer(from_fn(require_authentication));
+    let update_group = update_group
+        .layer(from_fn(enforce_policy))
+        .layer(Extension(Action("update_group")))
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_groups")))
+        .layer(from_fn(require_authentication));
+    let delete_group = delete_group
+        .layer(from_fn(enforce_policy))
+        .layer(Extension(Action("delete_group")))
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_groups")))
+        .layer(from_fn(require_authentication));
+    let add_group_member = add_group_member
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_groups")))
+        .layer(from_fn(require_authentication));
+    let remove_group_member = remove_group_member
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_groups")))
+        .layer(from_fn(require_authentication));
+    let add_nested_group = add_nested_group
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_groups")))
+        .layer(from_fn(require_authentication));
+    let remove_nested_group = remove_nested_group
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_groups")))
+        .layer(from_fn(require_authentication));
+    Router::new()
+        .route("/", post(create_group))
+        .route(
+            "/:group_id",
+            get(read_group).patch(update_group).delete(delete_group),
+        )
+        .route("/:group_id/members", post(add_group_member))
+        .route("/:group_id/members/:user_id", delete(remove_group_member))
+        .route("/:group_id/groups", post(add_nested_group))
+        .route(
+            "/:group_id/groups/:member_group_id",
+            delete(remove_nested_group),
+        )
+}
+
+async fn check_group_owner(
+    ctx: &ApiContext,
+    auth_ctx: &AuthContext,
+    group_id: &str,
+) -> Result<(), ApiError> {
+    let owner = query_scalar!("SELECT owner FROM groups WHERE group_id = $1", group_id)
+        .fetch_optional(&ctx.db)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    check_self_or_admin(ctx, auth_ctx, &owner).await
+}
+
+async fn check_group_reader(
+    ctx: &ApiContext,
+    auth_ctx: &AuthContext,
+    group_id: &str,
+) -> Result<(), ApiError> {
+    let user_id = auth_ctx
+        .subject
+        .as_deref()
+        .ok_or(ApiError::AuthenticationRequired)?;
+    let is_member = query_scalar!(
+        r#"WITH RECURSIVE user_groups(group_id) AS (
+            SELECT group_id FROM group_members WHERE user_id = $2
+            UNION
+            SELECT group_members.group_id FROM group_members JOIN user_groups ON group_members.member_group_id = user_groups.group_id
+        )
+        SELECT EXISTS (SELECT 1 FROM user_groups WHERE group_id = $1) AS "exists!""#,
+        group_id,
+        user_id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+    if is_member {
+        return Ok(());
+    }
+    check_group_owner(ctx, auth_ctx, group_id).await
+}
+
+#[derive(Deserialize)]
+struct CreateGroupPayload {
+    name: String,
+}
+
+#[derive(Serialize)]
+struct CreateGroupBody {
+    name: String,
+    owner: String,
+    uri: String,
+}
+
+async fn create_group(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    OriginalUri(uri): OriginalUri,
+    Json(payload): Json<CreateGroupPayload>,
+) -> Result<CreatedJson<CreateGroupBody>, ApiError> {
+    if !USER_REGEX.is_match(&payload.name) {
+        return Err(ApiError::BadRequest("invalid group name".to_string()));
+    }
+    let owner = auth_ctx
+        .subject
+        .clone()
+        .ok_or(ApiError::AuthenticationRequired)?;
+    query!(
+        "INSERT INTO groups (group_id, owner) VALUES ($1, $2)",
+        payload.name,
+        owner
+    )
+    .execute(&ctx.db)
+    .await
+    .map_err(|error| map_constraint_error(error, "group name already exists", "unknown user"))?;
+    let uri = format!("{}/{}", uri, payload.name);
+    Ok(CreatedJson(
+        uri.clone(),
+        CreateGroupBody {
+            name: payload.name,
+            owner,
+            uri,
+        },
+    ))
+}
+
+#[derive(Serialize)]
+struct GroupBody {
+    name: String,
+    owner: String,
+    created: DateTime<Utc>,
+    members: Vec<String>,
+    groups: Vec<String>,
+}
+
+async fn load_group(ctx: &ApiContext, group_id: String) -> Result<Json<GroupBody>, ApiError> {
+    let group = query!(
+        "SELECT group_id, owner, created FROM groups WHERE group_id = $1",
+        group_id
+    )
+    .fetch_optional(&ctx.db)
+    .await?
+    .ok_or(ApiError::NotFound)?;
+    let members = query_scalar!(
+        r#"SELECT user_id AS "user_id!" FROM group_members WHERE group_id = $1 AND user_id IS NOT NULL ORDER BY user_id"#,
+        group_id
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+    let groups = query_scalar!(
+        r#"SELECT member_group_id AS "member_group_id!" FROM group_members WHERE group_id = $1 AND member_group_id IS NOT NULL ORDER BY member_group_id"#,
+        group_id
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+    Ok(Json(GroupBody {
+        name: group.group_id,
+        owner: group.owner,
+        created: group.created,
+        members,
+        groups,
+    }))
+}
+
+async fn read_group(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    Path(group_id): Path<String>,
+) -> Result<Json<GroupBody>, ApiError> {
+    check_group_reader(&ctx, &auth_ctx, &group_id).await?;
+    load_group(&ctx, group_id).await
+}
+
+#[derive(Deserialize)]
+struct UpdateGroupPayload {
+    owner: String,
+}
+
+async fn update_group(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    Path(group_id): Path<String>,
+    Json(payload): Json<UpdateGroupPayload>,
+) -> Result<Json<GroupBody>, ApiError> {
+    check_group_owner(&ctx, &auth_ctx, &group_id).await?;
+    query!(
+        "UPDATE groups SET owner = $2 WHERE group_id = $1",
+        group_id,
+        payload.owner
+    )
+    .execute(&ctx.db)
+    .await
+    .map_err(|error| map_constraint_error(error, "group name already exists", "unknown user"))?;
+    load_group(&ctx, group_id).await
+}
+
+#[derive(Serialize)]
+struct DeleteGroupBody {}
+
+async fn delete_group(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    Path(group_id): Path<String>,
+) -> Result<Json<DeleteGroupBody>, ApiError> {
+    check_group_owner(&ctx, &auth_ctx, &group_id).await?;
+    let mut transaction = ctx.db.begin().await?;
+    query!(
+        "DELETE FROM group_members WHERE group_id = $1 OR member_group_id = $1",
+        group_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    query!("DELETE FROM permissions WHERE group_id = $1", group_id)
+        .execute(&mut transaction)
+        .await?;
+    query!("DELETE FROM groups WHERE group_id = $1", group_id)
+        .execute(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(Json(DeleteGroupBody {}))
+}
+
+#[derive(Deserialize)]
+struct AddGroupMemberPayload {
+    username: String,
+}
+
+#[derive(Serialize)]
+struct GroupMemberBody {
+    uri: String,
+}
+
+async fn add_group_member(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    OriginalUri(uri): OriginalUri,
+    Path(group_id): Path<String>,
+    Json(payload): Json<AddGroupMemberPayload>,
+) -> Result<CreatedJson<GroupMemberBody>, ApiError> {
+    check_group_owner(&ctx, &auth_ctx, &group_id).await?;
+    query!(
+        "INSERT INTO group_members (group_id, user_id) VALUES ($1, $2)",
+        group_id,
+        payload.username
+    )
+    .execute(&ctx.db)
+    .await
+    .map_err(|error| map_constraint_error(error, "user is already a member", "unknown user"))?;
+    let uri = format!("{}/{}", uri, payload.username);
+    Ok(CreatedJson(uri.clone(), GroupMemberBody { uri }))
+}
+
+async fn remove_group_member(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    Path((group_id, user_id)): Path<(String, String)>,
+) -> Result<Json<DeleteGroupBody>, ApiError> {
+    check_group_owner(&ctx, &auth_ctx, &group_id).await?;
+    let result = query!(
+        "DELETE FROM group_members WHERE group_id = $1 AND user_id = $2",
+        group_id,
+        user_id
+    )
+    .execute(&ctx.db)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound);
+    }
+    Ok(Json(DeleteGroupBody {}))
+}
+
+#[derive(Deserialize)]
+struct AddNestedGroupPayload {
+    group: String,
+}
+
+async fn add_nested_group(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    OriginalUri(uri): OriginalUri,
+    Path(group_id): Path<String>,
+    Json(payload): Json<AddNestedGroupPayload>,
+) -> Result<CreatedJson<GroupMemberBody>, ApiError> {
+    check_group_owner(&ctx, &auth_ctx, &group_id).await?;
+    let mut transaction = ctx.db.begin().await?;
+    query!("LOCK TABLE group_members IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut transaction)
+        .await?;
+    let creates_cycle = query_scalar!(
+        r#"WITH RECURSIVE nested_groups(group_id) AS (
+            SELECT $1::VARCHAR
+            UNION
+            SELECT group_members.member_group_id FROM group_members JOIN nested_groups ON group_members.group_id = nested_groups.group_id
+        )
+        SELECT EXISTS (SELECT 1 FROM nested_groups WHERE group_id = $2) AS "exists!""#,
+        payload.group,
+        group_id
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+    if creates_cycle {
+        return Err(ApiError::BadRequest(
+            "nesting this group would create a cycle".to_string(),
+        ));
+    }
+    query!(
+        "INSERT INTO group_members (group_id, member_group_id) VALUES ($1, $2)",
+        group_id,
+        payload.group
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(|error| map_constraint_error(error, "group is already a member", "unknown group"))?;
+    transaction.commit().await?;
+    let uri = format!("{}/{}", uri, payload.group);
+    Ok(CreatedJson(uri.clone(), GroupMemberBody { uri }))
+}
+
+async fn remove_nested_group(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    Path((group_id, member_group_id)): Path<(String, String)>,
+) -> Result<Json<DeleteGroupBody>, ApiError> {
+    check_group_owner(&ctx, &auth_ctx, &group_id).await?;
+    let result = query!(
+        "DELETE FROM group_members WHERE group_id = $1 AND member_group_id = $2",
+        group_id,
+        member_group_id
+    )
+    .execute(&ctx.db)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound);
+    }
+    Ok(Json(DeleteGroupBody {}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middlewares::{
+        authenticate, load_permission,
+        tests::{basic_request, context, create_user, delete_user, random_name, with_json},
+    };
+    use http::{Method, StatusCode};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    fn app(ctx: &ApiContext) -> Router {
+        Router::new()
+            .nest("/groups", router())
+            .layer(from_fn(authenticate))
+            .layer(Extension(ctx.clone()))
+    }
+
+    async fn insert_group(ctx: &ApiContext, owner: &str) -> String {
+        let group_id = random_name();
+        query!(
+            "INSERT INTO groups (group_id, owner) VALUES ($1, $2)",
+            group_id,
+            owner
+        )
+        .execute(&ctx.db)
+        .await
+        .unwrap();
+        group_id
+    }
+
+    async fn nest(ctx: &ApiContext, owner: &str, group_id: &str, member: &str) -> StatusCode {
+        let uri = format!("/groups/{}/groups", group_id);
+        let request = with_json(
+            basic_request(Method::POST, &uri, owner),
+            json!({ "group": member }),
+        );
+        app(ctx).oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn group_cannot_contain_itself() {
+        let ctx = context().await;
+        let owner = create_user(&ctx).await;
+        let group = insert_group(&ctx, &owner).await;
+        assert_eq!(
+            nest(&ctx, &owner, &group, &group).await,
+            StatusCode::BAD_REQUEST
+        );
+        delete_user(&ctx, &owner).await;
+    }
+
+    #[tokio::test]
+    async fn indirect_cycles_are_rejected() {
+        let ctx = context().await;
+        let owner = create_user(&ctx).await;
+        let outer = insert_group(&ctx, &owner).await;
+        let middle = insert_group(&ctx, &owner).await;
+        let inner = insert_group(&ctx, &owner).await;
+        assert_eq!(
+            nest(&ctx, &owner, &outer, &middle).await,
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            nest(&ctx, &owner, &middle, &inner).await,
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            nest(&ctx, &owner, &inner, &outer).await,
+            StatusCode::BAD_REQUEST
+        );
+        delete_user(&ctx, &owner).await;
+    }
+
+    #[tokio::test]
+    async fn members_of_nested_groups_inherit_space_permissions() {
+        let ctx = context().await;
+        let owner = create_user(&ctx).await;
+        let member = create_user(&ctx).await;
+        let outsider = create_user(&ctx).await;
+        let outer = insert_group(&ctx, &owner).await;
+        let inner = insert_group(&ctx, &owner).await;
+        assert_eq!(
+            nest(&ctx, &owner, &outer, &inner).await,
+            StatusCode::CREATED
+        );
+        query!(
+            "INSERT INTO group_members (group_id, user_id) VALUES ($1, $2)",
+            inner,
+            member
+        )
+        .execute(&ctx.db)
+        .await
+        .unwrap();
+        let space_id = query_scalar!(
+            "INSERT INTO spaces (name, owner) VALUES ($1, $1) RETURNING space_id",
+            owner
+        )
+        .fetch_one(&ctx.db)
+        .await
+        .unwrap();
+        query!(
+            "INSERT INTO permissions (space_id, group_id, role) VALUES ($1, $2, 'member')",
+            space_id,
+            outer
+        )
+        .execute(&ctx.db)
+        .await
+        .unwrap();
+        let permission = load_permission(&ctx.db, space_id, &member).await.unwrap();
+        assert!(permission.read && permission.write);
+        assert!(!permission.delete && !permission.admin);
+        let permission = load_permission(&ctx.db, space_id, &outsider).await.unwrap();
+        assert!(!permission.read && !permission.write);
+        delete_user(&ctx, &member).await;
+        delete_user(&ctx, &outsider).await;
+        delete_user(&ctx, &owner).await;
+    }
+}
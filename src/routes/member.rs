@@ -0,0 +1,349 @@
+use crate::api::{Action, ApiContext, AuthContext, CreatedJson, Json, Path, Permission, Scope};
+use crate::error::ApiError;
+use crate::middlewares::{enforce_policy, load_permission, require_permission, require_scope};
+use crate::routes::{map_constraint_error, USER_REGEX};
+use axum::{
+    extract::OriginalUri,
+    handler::Handler,
+    middleware::from_fn,
+    routing::{get, patch},
+    Extension, Router,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_scalar};
+
+const READ: Permission = Permission {
+    read: true,
+    write: false,
+    delete: false,
+    admin: false,
+};
+const ADMIN: Permission = Permission {
+    read: false,
+    write: false,
+    delete: false,
+    admin: true,
+};
+
+pub fn router() -> Router {
+    let list_members = list_members
+        .layer(from_fn(enforce_policy))
+        .layer(Extension(Action("list_members")))
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("list_members")))
+        .layer(from_fn(require_permission))
+        .layer(Extension(READ));
+    let add_member = add_member
+        .layer(from_fn(enforce_policy))
+        .layer(Extension(Action("add_member")))
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_members")))
+        .layer(from_fn(require_permission))
+        .layer(Extension(ADMIN));
+    let update_member = update_member
+        .layer(from_fn(enforce_policy))
+        .layer(Extension(Action("update_member")))
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_members")))
+        .layer(from_fn(require_permission))
+        .layer(Extension(ADMIN));
+    let remove_member = remove_member
+        .layer(from_fn(enforce_policy))
+        .layer(Extension(Action("remove_member")))
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_members")))
+        .layer(from_fn(require_permission))
+        .layer(Extension(ADMIN));
+    let list_group_grants = list_group_grants
+        .layer(from_fn(enforce_policy))
+        .layer(Extension(Action("list_group_grants")))
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("list_members")))
+        .layer(from_fn(require_permission))
+        .layer(Extension(READ));
+    let add_group_grant = add_group_grant
+        .layer(from_fn(enforce_policy))
+        .layer(Extension(Action("add_group_grant")))
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_members")))
+        .layer(from_fn(require_permission))
+        .layer(Extension(ADMIN));
+    let update_group_grant = update_group_grant
+        .layer(from_fn(enforce_policy))
+        .layer(Extension(Action("update_group_grant")))
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_members")))
+        .layer(from_fn(require_permission))
+        .layer(Extension(ADMIN));
+    let remove_group_grant = remove_group_grant
+        .layer(from_fn(enforce_policy))
+        .layer(Extension(Action("remove_group_grant")))
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_members")))
+        .layer(from_fn(require_permission))
+        .layer(Extension(ADMIN));
+    Router::new()
+        .route("/:space_id/members", get(list_members).post(add_member))
+        .route(
+            "/:space_id/members/:user_id",
+            patch(update_member).delete(remove_member),
+        )
+        .route(
+            "/:space_id/groups",
+            get(list_group_grants).post(add_group_grant),
+        )
+        .route(
+            "/:space_id/groups/:group_id",
+            patch(update_group_grant).delete(remove_group_grant),
+        )
+}
+
+async fn check_grant(
+    ctx: &ApiContext,
+    auth_ctx: &AuthContext,
+    space_id: i32,
+    role: &str,
+) -> Result<String, ApiError> {
+    let perms = query_scalar!("SELECT perms FROM role_permissions WHERE role = $1", role)
+        .fetch_optional(&ctx.db)
+        .await?
+        .ok_or_else(|| ApiError::BadRequest("unknown role".to_string()))?;
+    let granter = auth_ctx.subject.as_deref().unwrap_or_default();
+    let granter_permission = load_permission(&ctx.db, space_id, granter).await?;
+    if !Permission::from(perms.as_str()).is_allowed(&granter_permission) {
+        return Err(ApiError::Forbidden);
+    }
+    Ok(perms)
+}
+
+async fn check_not_owner(ctx: &ApiContext, space_id: i32, user_id: &str) -> Result<(), ApiError> {
+    let owner = query_scalar!("SELECT owner FROM spaces WHERE space_id = $1", space_id)
+        .fetch_optional(&ctx.db)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    if owner == user_id {
+        return Err(ApiError::BadRequest(
+            "cannot change the role of the space owner".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct MemberBody {
+    username: String,
+    role: String,
+    permissions: String,
+}
+
+async fn list_members(
+    ctx: Extension<ApiContext>,
+    Path(space_id): Path<i32>,
+) -> Result<Json<Vec<MemberBody>>, ApiError> {
+    let members = query!(
+        r#"SELECT user_id AS "user_id!", permissions.role, perms FROM permissions JOIN role_permissions ON permissions.role = role_permissions.role WHERE space_id = $1 AND user_id IS NOT NULL ORDER BY user_id"#,
+        space_id
+    )
+    .fetch_all(&ctx.db)
+    .await?
+    .into_iter()
+    .map(|record| MemberBody {
+        username: record.user_id,
+        role: record.role,
+        permissions: record.perms,
+    })
+    .collect();
+    Ok(Json(members))
+}
+
+#[derive(Deserialize)]
+struct AddMemberPayload {
+    username: String,
+    role: String,
+}
+
+async fn add_member(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    OriginalUri(uri): OriginalUri,
+    Path(space_id): Path<i32>,
+    Json(payload): Json<AddMemberPayload>,
+) -> Result<CreatedJson<MemberBody>, ApiError> {
+    if !USER_REGEX.is_match(&payload.username) {
+        return Err(ApiError::BadRequest("invalid user name".to_string()));
+    }
+    let perms = check_grant(&ctx, &auth_ctx, space_id, &payload.role).await?;
+    query!(
+        "INSERT INTO permissions (space_id, user_id, role) VALUES ($1, $2, $3)",
+        space_id,
+        payload.username,
+        payload.role
+    )
+    .execute(&ctx.db)
+    .await
+    .map_err(|error| map_constraint_error(error, "user is already a member", "unknown user"))?;
+    let uri = format!("{}/{}", uri, payload.username);
+    Ok(CreatedJson(
+        uri,
+        MemberBody {
+            username: payload.username,
+            role: payload.role,
+            permissions: perms,
+        },
+    ))
+}
+
+#[derive(Deserialize)]
+struct UpdateMemberPayload {
+    role: String,
+}
+
+async fn update_member(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    Path((space_id, user_id)): Path<(i32, String)>,
+    Json(payload): Json<UpdateMemberPayload>,
+) -> Result<Json<MemberBody>, ApiError> {
+    check_not_owner(&ctx, space_id, &user_id).await?;
+    let perms = check_grant(&ctx, &auth_ctx, space_id, &payload.role).await?;
+    let result = query!(
+        "UPDATE permissions SET role = $3 WHERE space_id = $1 AND user_id = $2",
+        space_id,
+        user_id,
+        payload.role
+    )
+    .execute(&ctx.db)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound);
+    }
+    Ok(Json(MemberBody {
+        username: user_id,
+        role: payload.role,
+        permissions: perms,
+    }))
+}
+
+#[derive(Serialize)]
+struct RemoveMemberBody {}
+
+async fn remove_member(
+    ctx: Extension<ApiContext>,
+    Path((space_id, user_id)): Path<(i32, String)>,
+) -> Result<Json<RemoveMemberBody>, ApiError> {
+    check_not_owner(&ctx, space_id, &user_id).await?;
+    let result = query!(
+        "DELETE FROM permissions WHERE space_id = $1 AND user_id = $2",
+        space_id,
+        user_id
+    )
+    .execute(&ctx.db)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound);
+    }
+    Ok(Json(RemoveMemberBody {}))
+}
+
+#[derive(Serialize)]
+struct GroupGrantBody {
+    group: String,
+    role: String,
+    permissions: String,
+}
+
+async fn list_group_grants(
+    ctx: Extension<ApiContext>,
+    Path(space_id): Path<i32>,
+) -> Result<Json<Vec<GroupGrantBody>>, ApiError> {
+    let grants = query!(
+        r#"SELECT group_id AS "group_id!", permissions.role, perms FROM permissions JOIN role_permissions ON permissions.role = role_permissions.role WHERE space_id = $1 AND group_id IS NOT NULL ORDER BY group_id"#,
+        space_id
+    )
+    .fetch_all(&ctx.db)
+    .await?
+    .into_iter()
+    .map(|record| GroupGrantBody {
+        group: record.group_id,
+        role: record.role,
+        permissions: record.perms,
+    })
+    .collect();
+    Ok(Json(grants))
+}
+
+#[derive(Deserialize)]
+struct AddGroupGrantPayload {
+    group: String,
+    role: String,
+}
+
+async fn add_group_grant(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    OriginalUri(uri): OriginalUri,
+    Path(space_id): Path<i32>,
+    Json(payload): Json<AddGroupGrantPayload>,
+) -> Result<CreatedJson<GroupGrantBody>, ApiError> {
+    let perms = check_grant(&ctx, &auth_ctx, space_id, &payload.role).await?;
+    query!(
+        "INSERT INTO permissions (space_id, group_id, role) VALUES ($1, $2, $3)",
+        space_id,
+        payload.group,
+        payload.role
+    )
+    .execute(&ctx.db)
+    .await
+    .map_err(|error| map_constraint_error(error, "group already has access", "unknown group"))?;
+    let uri = format!("{}/{}", uri, payload.group);
+    Ok(CreatedJson(
+        uri,
+        GroupGrantBody {
+            group: payload.group,
+            role: payload.role,
+            permissions: perms,
+        },
+    ))
+}
+
+async fn update_group_grant(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    Path((space_id, group_id)): Path<(i32, String)>,
+    Json(payload): Json<UpdateMemberPayload>,
+) -> Result<Json<GroupGrantBody>, ApiError> {
+    let perms = check_grant(&ctx, &auth_ctx, space_id, &payload.role).await?;
+    let result = query!(
+        "UPDATE permissions SET role = $3 WHERE space_id = $1 AND group_id = $2",
+        space_id,
+        group_id,
+        payload.role
+    )
+    .execute(&ctx.db)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound);
+    }
+    Ok(Json(GroupGrantBody {
+        group: group_id,
+        role: payload.role,
+        permissions: perms,
+    }))
+}
+
+async fn remove_group_grant(
+    ctx: Extension<ApiContext>,
+    Path((space_id, group_id)): Path<(i32, String)>,
+) -> Result<Json<RemoveMemberBody>, ApiError> {
+    let result = query!(
+        "DELETE FROM permissions WHERE space_id = $1 AND group_id = $2",
+        space_id,
+        group_id
+    )
+    .execute(&ctx.db)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound);
+    }
+    Ok(Json(RemoveMemberBody {}))
+}
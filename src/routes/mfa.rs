@@ -0,0 +1,151 @@
+use crate::api::{ApiContext, AuthContext, CreatedJson, Json, Path, Scope, StepUp};
+use crate::error::ApiError;
+use crate::mfa;
+use crate::middlewares::{require_authentication, require_scope, require_step_up};
+use crate::routes::check_subject;
+use axum::{
+    extract::OriginalUri, handler::Handler, middleware::from_fn, routing::post, Extension, Router,
+};
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+use sqlx::query;
+
+const MFA_CHANGE_MAX_AUTH_AGE_MINUTES: i64 = 5;
+
+pub fn router() -> Router {
+    let enroll = enroll
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_mfa")))
+        .layer(from_fn(require_step_up))
+        .layer(Extension(StepUp::recent(Duration::minutes(
+            MFA_CHANGE_MAX_AUTH_AGE_MINUTES,
+        ))));
+    let disable = disable
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_mfa")))
+        .layer(from_fn(require_step_up))
+        .layer(Extension(StepUp::recent(Duration::minutes(
+            MFA_CHANGE_MAX_AUTH_AGE_MINUTES,
+        ))));
+    let confirm = confirm
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_mfa")))
+        .layer(from_fn(require_authentication));
+    let regenerate_recovery_codes = regenerate_recovery_codes
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_mfa")))
+        .layer(from_fn(require_step_up))
+        .layer(Extension(StepUp::mfa()));
+    Router::new()
+        .route("/:user_id/mfa", post(enroll).delete(disable))
+        .route("/:user_id/mfa/confirm", post(confirm))
+        .route(
+            "/:user_id/mfa/recovery-codes",
+            post(regenerate_recovery_codes),
+        )
+}
+
+#[derive(Serialize)]
+struct EnrollBody {
+    secret: String,
+    uri: String,
+}
+
+async fn enroll(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    OriginalUri(uri): OriginalUri,
+    Path(user_id): Path<String>,
+) -> Result<CreatedJson<EnrollBody>, ApiError> {
+    check_subject(&auth_ctx, &user_id)?;
+    let secret = mfa::generate_secret();
+    let result = query!(
+        "INSERT INTO user_mfa (user_id, totp_secret) VALUES ($1, $2) ON CONFLICT (user_id) DO UPDATE SET totp_secret = EXCLUDED.totp_secret, last_used_step = NULL WHERE user_mfa.confirmed = false",
+        user_id,
+        secret
+    )
+    .execute(&ctx.db)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::Conflict(
+            "two-factor authentication is already enabled".to_string(),
+        ));
+    }
+    let body = EnrollBody {
+        uri: mfa::otpauth_uri(&user_id, &secret),
+        secret,
+    };
+    Ok(CreatedJson(uri.to_string(), body))
+}
+
+#[derive(Deserialize)]
+struct ConfirmPayload {
+    code: String,
+}
+
+#[derive(Serialize)]
+struct RecoveryCodesBody {
+    recovery_codes: Vec<String>,
+}
+
+async fn confirm(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    Path(user_id): Path<String>,
+    Json(payload): Json<ConfirmPayload>,
+) -> Result<Json<RecoveryCodesBody>, ApiError> {
+    check_subject(&auth_ctx, &user_id)?;
+    if !mfa::check_totp(&ctx.db, &user_id, &payload.code, false).await? {
+        return Err(ApiError::BadRequest(
+            "invalid verification code".to_string(),
+        ));
+    }
+    query!(
+        "UPDATE user_mfa SET confirmed = true WHERE user_id = $1",
+        user_id
+    )
+    .execute(&ctx.db)
+    .await?;
+    let recovery_codes = mfa::replace_recovery_codes(&ctx.db, &user_id).await?;
+    Ok(Json(RecoveryCodesBody { recovery_codes }))
+}
+
+async fn regenerate_recovery_codes(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    Path(user_id): Path<String>,
+) -> Result<Json<RecoveryCodesBody>, ApiError> {
+    check_subject(&auth_ctx, &user_id)?;
+    if !mfa::is_enrolled(&ctx.db, &user_id).await? {
+        return Err(ApiError::NotFound);
+    }
+    let recovery_codes = mfa::replace_recovery_codes(&ctx.db, &user_id).await?;
+    Ok(Json(RecoveryCodesBody { recovery_codes }))
+}
+
+#[derive(Serialize)]
+struct DisableBody {}
+
+async fn disable(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    Path(user_id): Path<String>,
+) -> Result<Json<DisableBody>, ApiError> {
+    check_subject(&auth_ctx, &user_id)?;
+    let confirmed = mfa::is_enrolled(&ctx.db, &user_id).await?;
+    if confirmed && !auth_ctx.mfa {
+        return Err(ApiError::MfaRequired);
+    }
+    let mut transaction = ctx.db.begin().await?;
+    query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+        .execute(&mut transaction)
+        .await?;
+    let result = query!("DELETE FROM user_mfa WHERE user_id = $1", user_id)
+        .execute(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound);
+    }
+    Ok(Json(DisableBody {}))
+}
@@ -1,10 +1,63 @@
+pub mod capability;
+pub mod group;
+pub mod member;
+pub mod mfa;
 pub mod moderator;
+pub mod oauth2;
+pub mod password;
+pub mod session;
 pub mod space;
 pub mod user;
+pub mod well_known;
 
+use crate::api::{ApiContext, AuthContext};
+use crate::error::ApiError;
 use lazy_static::lazy_static;
 use regex::Regex;
+use sqlx::query_scalar;
 
 lazy_static! {
     pub static ref USER_REGEX: Regex = Regex::new("^[a-zA-Z][a-zA-Z0-9]{1,29}$").unwrap();
 }
+
+pub fn check_subject(auth_ctx: &AuthContext, user_id: &str) -> Result<(), ApiError> {
+    match auth_ctx.subject.as_deref() {
+        Some(subject) if subject == user_id => Ok(()),
+        Some(_) => Err(ApiError::Forbidden),
+        None => Err(ApiError::AuthenticationRequired),
+    }
+}
+
+pub async fn check_self_or_admin(
+    ctx: &ApiContext,
+    auth_ctx: &AuthContext,
+    user_id: &str,
+) -> Result<(), ApiError> {
+    let subject = auth_ctx
+        .subject
+        .as_deref()
+        .ok_or(ApiError::AuthenticationRequired)?;
+    if subject == user_id {
+        return Ok(());
+    }
+    let is_admin = query_scalar!("SELECT is_admin FROM users WHERE user_id = $1", subject)
+        .fetch_optional(&ctx.db)
+        .await?
+        .unwrap_or(false);
+    if !is_admin {
+        return Err(ApiError::Forbidden);
+    }
+    Ok(())
+}
+
+pub fn map_constraint_error(error: sqlx::Error, conflict: &str, unknown: &str) -> ApiError {
+    match error {
+        sqlx::Error::Database(db_err) if db_err.code().unwrap_or_default() == "23505" => {
+            ApiError::Conflict(conflict.to_string())
+        }
+        sqlx::Error::Database(db_err) if db_err.code().unwrap_or_default() == "23503" => {
+            ApiError::BadRequest(unknown.to_string())
+        }
+        _ => error.into(),
+    }
+}
@@ -1,17 +1,32 @@
-use crate::api::{ApiContext, Json, Path, Permission};
+use crate::api::{Action, ApiContext, Json, Path, Permission, Scope, StepUp};
 use crate::error::ApiError;
-use crate::middlewares::require_permission;
+use crate::middlewares::{enforce_policy, require_capability, require_scope, require_step_up};
 use axum::{handler::Handler, middleware::from_fn, routing::delete, Extension, Router};
+use chrono::Duration;
 use serde::Serialize;
 use sqlx::query;
 
+const DELETE_MESSAGE_MAX_AUTH_AGE_MINUTES: i64 = 5;
+
 pub fn router() -> Router {
     let delete_message = delete_message
-        .layer(from_fn(require_permission))
+        .layer(from_fn(enforce_policy))
+        .layer(Extension(Action("delete_message")))
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("delete_message")))
+        // Runs after the capability check, so a delete capability only lends
+        // its permission to a user who has recently authenticated, with their
+        // second factor if they have enrolled one.
+        .layer(from_fn(require_step_up))
+        .layer(Extension(StepUp::recent_with_mfa(Duration::minutes(
+            DELETE_MESSAGE_MAX_AUTH_AGE_MINUTES,
+        ))))
+        .layer(from_fn(require_capability))
         .layer(Extension(Permission {
             read: false,
             write: false,
             delete: true,
+            admin: false,
         }));
     Router::new().route("/:space_id/messages/:msg_id", delete(delete_message))
 }
@@ -32,3 +47,141 @@ async fn delete_message(
     .await?;
     Ok(Json(DeleteMessageBody {}))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::ApiContext;
+    use crate::capability;
+    use crate::middlewares::{
+        authenticate,
+        tests::{basic_request, bearer_request, context, create_user, delete_user},
+    };
+    use crate::tokens::{Token, AUTH_TIME_ATTRIBUTE, MFA_ATTRIBUTE};
+    use axum::body::Body;
+    use chrono::Utc;
+    use http::{Method, Request, StatusCode};
+    use sqlx::query_scalar;
+    use tower::ServiceExt;
+
+    fn app(ctx: &ApiContext) -> Router {
+        Router::new()
+            .nest("/spaces", router())
+            .layer(from_fn(authenticate))
+            .layer(Extension(ctx.clone()))
+    }
+
+    async fn token(ctx: &ApiContext, username: &str, mfa: bool) -> String {
+        let mut token = Token::new(Utc::now() + ctx.token_expiry, username.to_string());
+        token
+            .attributes
+            .insert(MFA_ATTRIBUTE.to_string(), mfa.to_string());
+        token.attributes.insert(
+            AUTH_TIME_ATTRIBUTE.to_string(),
+            Utc::now().timestamp().to_string(),
+        );
+        ctx.tokens.create(&token).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn enrolled_moderators_need_mfa_to_delete_messages() {
+        let ctx = context().await;
+        let username = create_user(&ctx).await;
+        let space_id = query_scalar!(
+            "INSERT INTO spaces (name, owner) VALUES ($1, $1) RETURNING space_id",
+            username
+        )
+        .fetch_one(&ctx.db)
+        .await
+        .unwrap();
+        query!(
+            "INSERT INTO permissions (space_id, user_id, role) VALUES ($1, $2, 'moderator')",
+            space_id,
+            username
+        )
+        .execute(&ctx.db)
+        .await
+        .unwrap();
+        let msg_id = query_scalar!(
+            "INSERT INTO messages (space_id, author, msg_text) VALUES ($1, $2, 'hello') RETURNING msg_id",
+            space_id,
+            username
+        )
+        .fetch_one(&ctx.db)
+        .await
+        .unwrap();
+        query!(
+            "INSERT INTO user_mfa (user_id, totp_secret, confirmed) VALUES ($1, 'secret', true)",
+            username
+        )
+        .execute(&ctx.db)
+        .await
+        .unwrap();
+        let uri = format!("/spaces/{}/messages/{}", space_id, msg_id);
+        let token_id = token(&ctx, &username, false).await;
+        let response = app(&ctx)
+            .oneshot(bearer_request(Method::DELETE, &uri, &token_id))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let token_id = token(&ctx, &username, true).await;
+        let response = app(&ctx)
+            .oneshot(bearer_request(Method::DELETE, &uri, &token_id))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        delete_user(&ctx, &username).await;
+    }
+
+    #[tokio::test]
+    async fn delete_capabilities_need_an_authenticated_holder() {
+        let ctx = context().await;
+        let owner = create_user(&ctx).await;
+        let holder = create_user(&ctx).await;
+        let space_id = query_scalar!(
+            "INSERT INTO spaces (name, owner) VALUES ($1, $1) RETURNING space_id",
+            owner
+        )
+        .fetch_one(&ctx.db)
+        .await
+        .unwrap();
+        query!(
+            "INSERT INTO permissions (space_id, user_id, role) VALUES ($1, $2, 'owner')",
+            space_id,
+            owner
+        )
+        .execute(&ctx.db)
+        .await
+        .unwrap();
+        let msg_id = query_scalar!(
+            "INSERT INTO messages (space_id, author, msg_text) VALUES ($1, $2, 'hello') RETURNING msg_id",
+            space_id,
+            owner
+        )
+        .fetch_one(&ctx.db)
+        .await
+        .unwrap();
+        let expiry = Utc::now() + ctx.capability_expiry;
+        let access_token = capability::create(&ctx.db, space_id, "rwd", &owner, expiry)
+            .await
+            .unwrap();
+        let uri = format!(
+            "/spaces/{}/messages/{}?access_token={}",
+            space_id, msg_id, access_token
+        );
+        let request = Request::builder()
+            .method(Method::DELETE)
+            .uri(&uri)
+            .body(Body::empty())
+            .unwrap();
+        let response = app(&ctx).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app(&ctx)
+            .oneshot(basic_request(Method::DELETE, &uri, &holder))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        delete_user(&ctx, &holder).await;
+        delete_user(&ctx, &owner).await;
+    }
+}
@@ -0,0 +1,720 @@
+use crate::api::{ApiContext, AuthContext, CreatedJson, Form, Json, SCOPES};
+use crate::error::ApiError;
+use crate::middlewares::require_authentication;
+use crate::tokens::{
+    hash, random_id,
+    refresh::{RefreshToken, RefreshTokenStore},
+    Token, CLIENT_ID_ATTRIBUTE, SCOPE_ATTRIBUTE,
+};
+use anyhow::Context;
+use axum::{
+    extract::{OriginalUri, TypedHeader},
+    handler::Handler,
+    headers::{authorization::Basic, Authorization},
+    middleware::from_fn,
+    response::{IntoResponse, Response},
+    routing::post,
+    Extension, Router,
+};
+use chrono::{Duration, Utc};
+use http::StatusCode;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{query, PgPool};
+use std::collections::HashMap;
+use subtle::ConstantTimeEq;
+use validator::Validate;
+
+pub const OAUTH2_TOKEN_PATH: &str = "/oauth2/token";
+
+const AUTHORIZATION_CODE_EXPIRY_MINUTES: i64 = 5;
+
+pub fn router() -> Router {
+    let register_client = register_client.layer(from_fn(require_authentication));
+    let authorize = authorize.layer(from_fn(require_authentication));
+    Router::new()
+        .route("/clients", post(register_client))
+        .route("/authorize", post(authorize))
+        .route("/token", post(token))
+}
+
+fn parse_scope(scope: &str) -> Result<Vec<&str>, ApiError> {
+    let scopes: Vec<&str> = scope.split_whitespace().collect();
+    if let Some(unknown) = scopes.iter().find(|s| !SCOPES.contains(s)) {
+        return Err(ApiError::BadRequest(format!("unknown scope: {}", unknown)));
+    }
+    Ok(scopes)
+}
+
+fn is_valid_redirect_uri(uri: &str) -> bool {
+    match Url::parse(uri) {
+        Ok(url) => {
+            let is_localhost = matches!(url.host_str(), Some("localhost") | Some("127.0.0.1"));
+            url.fragment().is_none()
+                && (url.scheme() == "https" || (url.scheme() == "http" && is_localhost))
+        }
+        Err(_) => false,
+    }
+}
+
+#[derive(Deserialize, Validate)]
+struct RegisterClientPayload {
+    #[validate(length(min = 1, max = 255))]
+    name: String,
+    #[validate(length(min = 1, max = 5))]
+    redirect_uris: Vec<String>,
+    scope: Option<String>,
+    #[serde(default = "default_confidential")]
+    confidential: bool,
+}
+
+fn default_confidential() -> bool {
+    true
+}
+
+#[derive(Serialize)]
+struct RegisterClientBody {
+    client_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_secret: Option<String>,
+    name: String,
+    redirect_uris: Vec<String>,
+    scope: String,
+}
+
+async fn register_client(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    OriginalUri(uri): OriginalUri,
+    Json(payload): Json<RegisterClientPayload>,
+) -> Result<CreatedJson<RegisterClientBody>, ApiError> {
+    if let Err(e) = payload.validate() {
+        if e.errors().contains_key("name") {
+            return Err(ApiError::BadRequest("invalid client name".to_string()));
+        }
+        if e.errors().contains_key("redirect_uris") {
+            return Err(ApiError::BadRequest(
+                "between 1 and 5 redirect URIs must be registered".to_string(),
+            ));
+        }
+    }
+    let owner = auth_ctx
+        .subject
+        .clone()
+        .ok_or(ApiError::AuthenticationRequired)?;
+    // Only a full-scope session may register clients, otherwise a narrowly
+    // scoped token could mint itself a client with a wider scope.
+    if let Some(missing) = SCOPES.iter().find(|s| !auth_ctx.has_scope(s)) {
+        return Err(ApiError::InsufficientScope(missing));
+    }
+    if let Some(uri) = payload
+        .redirect_uris
+        .iter()
+        .find(|uri| !is_valid_redirect_uri(uri))
+    {
+        return Err(ApiError::BadRequest(format!(
+            "invalid redirect URI: {}",
+            uri
+        )));
+    }
+    let redirect_uris = payload.redirect_uris.join(" ");
+    if redirect_uris.len() > 2048 {
+        return Err(ApiError::BadRequest("redirect URIs too long".to_string()));
+    }
+    let scope = match &payload.scope {
+        Some(scope) => parse_scope(scope)?.join(" "),
+        None => SCOPES.join(" "),
+    };
+    let client_id = random_id();
+    let client_secret = payload.confidential.then(random_id);
+    let client_secret_hash = client_secret.as_deref().map(hash);
+    query!(
+        "INSERT INTO oauth2_clients (client_id, client_secret_hash, name, owner, redirect_uris, scope) VALUES ($1, $2, $3, $4, $5, $6)",
+        client_id,
+        client_secret_hash,
+        payload.name,
+        owner,
+        redirect_uris,
+        scope
+    )
+    .execute(&ctx.db)
+    .await?;
+    let uri = format!("{}/{}", uri, client_id);
+    let body = RegisterClientBody {
+        client_id,
+        client_secret,
+        name: payload.name,
+        redirect_uris: payload.redirect_uris,
+        scope,
+    };
+    Ok(CreatedJson(uri, body))
+}
+
+#[derive(Deserialize)]
+struct AuthorizePayload {
+    response_type: String,
+    client_id: String,
+    redirect_uri: String,
+    scope: Option<String>,
+    state: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AuthorizeBody {
+    redirect_uri: String,
+}
+
+async fn authorize(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    Json(payload): Json<AuthorizePayload>,
+) -> Result<Json<AuthorizeBody>, ApiError> {
+    let username = auth_ctx
+        .subject
+        .as_deref()
+        .ok_or(ApiError::AuthenticationRequired)?;
+    let client = query!(
+        "SELECT client_secret_hash, redirect_uris, scope FROM oauth2_clients WHERE client_id = $1",
+        payload.client_id
+    )
+    .fetch_optional(&ctx.db)
+    .await?
+    .ok_or_else(|| ApiError::BadRequest("unknown client".to_string()))?;
+    if !client
+        .redirect_uris
+        .split_whitespace()
+        .any(|uri| uri == payload.redirect_uri)
+    {
+        return Err(ApiError::BadRequest(
+            "redirect URI is not registered for this client".to_string(),
+        ));
+    }
+    if payload.response_type != "code" {
+        return Err(ApiError::BadRequest(
+            "unsupported response type".to_string(),
+        ));
+    }
+    let scope = match &payload.scope {
+        Some(scope) => {
+            let scopes = parse_scope(scope)?;
+            let client_scopes: Vec<&str> = client.scope.split_whitespace().collect();
+            if let Some(exceeded) = scopes
+                .iter()
+                .find(|s| !client_scopes.contains(s) || !auth_ctx.has_scope(s))
+            {
+                return Err(ApiError::BadRequest(format!(
+                    "scope not permitted: {}",
+                    exceeded
+                )));
+            }
+            scopes.join(" ")
+        }
+        None => client
+            .scope
+            .split_whitespace()
+            .filter(|s| auth_ctx.has_scope(s))
+            .collect::<Vec<_>>()
+            .join(" "),
+    };
+    match (
+        &payload.code_challenge,
+        payload.code_challenge_method.as_deref(),
+    ) {
+        (Some(_), Some("S256")) => {}
+        (Some(_), _) => {
+            return Err(ApiError::BadRequest(
+                "code challenge method must be S256".to_string(),
+            ))
+        }
+        (None, _) if client.client_secret_hash.is_none() => {
+            return Err(ApiError::BadRequest(
+                "public clients must use PKCE".to_string(),
+            ))
+        }
+        (None, _) => {}
+    }
+    let code = random_id();
+    let expiry = Utc::now() + Duration::minutes(AUTHORIZATION_CODE_EXPIRY_MINUTES);
+    query!(
+        "INSERT INTO oauth2_codes (code_hash, client_id, user_id, redirect_uri, scope, code_challenge, expiry) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        hash(&code),
+        payload.client_id,
+        username,
+        payload.redirect_uri,
+        scope,
+        payload.code_challenge,
+        expiry
+    )
+    .execute(&ctx.db)
+    .await?;
+    let mut redirect_uri =
+        Url::parse(&payload.redirect_uri).context("registered redirect URI is invalid")?;
+    redirect_uri.query_pairs_mut().append_pair("code", &code);
+    if let Some(state) = &payload.state {
+        redirect_uri.query_pairs_mut().append_pair("state", state);
+    }
+    Ok(Json(AuthorizeBody {
+        redirect_uri: redirect_uri.to_string(),
+    }))
+}
+
+enum TokenError {
+    Api(ApiError),
+    OAuth2(StatusCode, &'static str, &'static str),
+}
+
+impl From<ApiError> for TokenError {
+    fn from(e: ApiError) -> Self {
+        TokenError::Api(e)
+    }
+}
+
+impl From<sqlx::Error> for TokenError {
+    fn from(e: sqlx::Error) -> Self {
+        TokenError::Api(e.into())
+    }
+}
+
+impl IntoResponse for TokenError {
+    fn into_response(self) -> Response {
+        match self {
+            TokenError::Api(e) => e.into_response(),
+            TokenError::OAuth2(status_code, error, description) => (
+                status_code,
+                Json(json!({
+                    "error": error,
+                    "error_description": description,
+                })),
+            )
+                .into_response(),
+        }
+    }
+}
+
+fn invalid_client() -> TokenError {
+    TokenError::OAuth2(
+        StatusCode::UNAUTHORIZED,
+        "invalid_client",
+        "client authentication failed",
+    )
+}
+
+fn invalid_grant() -> TokenError {
+    TokenError::OAuth2(
+        StatusCode::BAD_REQUEST,
+        "invalid_grant",
+        "the grant is invalid, expired or was issued to another client",
+    )
+}
+
+fn invalid_scope() -> TokenError {
+    TokenError::OAuth2(
+        StatusCode::BAD_REQUEST,
+        "invalid_scope",
+        "the requested scope exceeds the granted scope",
+    )
+}
+
+#[derive(Deserialize)]
+struct TokenPayload {
+    grant_type: String,
+    code: Option<String>,
+    redirect_uri: Option<String>,
+    code_verifier: Option<String>,
+    refresh_token: Option<String>,
+    scope: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TokenBody {
+    access_token: String,
+    token_type: &'static str,
+    expires_in: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+    scope: String,
+}
+
+struct Client {
+    client_id: String,
+    owner: String,
+    scope: String,
+    is_confidential: bool,
+}
+
+async fn authenticate_client(
+    ctx: &ApiContext,
+    basic_auth: Option<Authorization<Basic>>,
+    payload: &TokenPayload,
+) -> Result<Client, TokenError> {
+    let (client_id, client_secret) = match &basic_auth {
+        Some(basic_auth) => (basic_auth.username(), Some(basic_auth.password())),
+        None => (
+            payload.client_id.as_deref().ok_or_else(invalid_client)?,
+            payload.client_secret.as_deref(),
+        ),
+    };
+    let client = query!(
+        "SELECT client_secret_hash, owner, scope FROM oauth2_clients WHERE client_id = $1",
+        client_id
+    )
+    .fetch_optional(&ctx.db)
+    .await?
+    .ok_or_else(invalid_client)?;
+    match (&client.client_secret_hash, client_secret) {
+        (Some(secret_hash), Some(secret)) => {
+            if !bool::from(secret_hash.as_bytes().ct_eq(hash(secret).as_bytes())) {
+                return Err(invalid_client());
+            }
+        }
+        (Some(_), None) => return Err(invalid_client()),
+        (None, _) => {}
+    }
+    Ok(Client {
+        client_id: client_id.to_string(),
+        owner: client.owner,
+        scope: client.scope,
+        is_confidential: client.client_secret_hash.is_some(),
+    })
+}
+
+async fn issue_tokens(
+    ctx: &ApiContext,
+    username: String,
+    client_id: &str,
+    scope: String,
+    refresh_family_id: Option<String>,
+) -> Result<TokenBody, ApiError> {
+    let mut token = Token::new(Utc::now() + ctx.token_expiry, username.clone());
+    token
+        .attributes
+        .insert(SCOPE_ATTRIBUTE.to_string(), scope.clone());
+    token
+        .attributes
+        .insert(CLIENT_ID_ATTRIBUTE.to_string(), client_id.to_string());
+    let access_token = ctx.tokens.create(&token).await?;
+    let refresh_token = match refresh_family_id {
+        Some(family_id) => {
+            let refresh_token = RefreshToken {
+                expiry: Utc::now() + ctx.refresh_token_expiry,
+                username,
+                client_id: Some(client_id.to_string()),
+                scope: Some(scope.clone()),
+                family_id,
+                session_id: ctx.tokens.session_id(&access_token),
+                attributes: HashMap::new(),
+            };
+            Some(ctx.refresh_tokens.create(&refresh_token).await?)
+        }
+        None => None,
+    };
+    Ok(TokenBody {
+        access_token,
+        token_type: "Bearer",
+        expires_in: ctx.token_expiry.num_seconds(),
+        refresh_token,
+        scope,
+    })
+}
+
+fn narrow_scope(requested: Option<&str>, granted: &str) -> Result<String, TokenError> {
+    let granted: Vec<&str> = granted.split_whitespace().collect();
+    match requested {
+        Some(requested) => {
+            let requested: Vec<&str> = requested.split_whitespace().collect();
+            if requested.iter().any(|s| !granted.contains(s)) {
+                return Err(invalid_scope());
+            }
+            Ok(requested.join(" "))
+        }
+        None => Ok(granted.join(" ")),
+    }
+}
+
+fn verify_code_challenge(code_challenge: &str, code_verifier: &str) -> bool {
+    let is_valid_verifier = (43..=128).contains(&code_verifier.len())
+        && code_verifier
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-._~".contains(c));
+    is_valid_verifier
+        && bool::from(
+            hash(code_verifier)
+                .as_bytes()
+                .ct_eq(code_challenge.as_bytes()),
+        )
+}
+
+pub async fn delete_expired_codes(db: &PgPool) -> Result<u64, ApiError> {
+    let result = query!("DELETE FROM oauth2_codes WHERE expiry < now()")
+        .execute(db)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+async fn token(
+    ctx: Extension<ApiContext>,
+    basic_auth: Option<TypedHeader<Authorization<Basic>>>,
+    Form(payload): Form<TokenPayload>,
+) -> Result<Json<TokenBody>, TokenError> {
+    let client =
+        authenticate_client(&ctx, basic_auth.map(|TypedHeader(auth)| auth), &payload).await?;
+    let body = match payload.grant_type.as_str() {
+        "authorization_code" => {
+            let code = payload.code.as_deref().ok_or_else(invalid_grant)?;
+            let mut transaction = ctx.db.begin().await?;
+            let grant = query!(
+                "SELECT client_id, user_id, redirect_uri, scope, code_challenge, expiry, family_id FROM oauth2_codes WHERE code_hash = $1 FOR UPDATE",
+                hash(code)
+            )
+            .fetch_optional(&mut transaction)
+            .await?
+            .ok_or_else(invalid_grant)?;
+            // Used codes are kept until they expire and remember the refresh
+            // family they were exchanged for, so that a replayed code revokes
+            // the tokens issued from it (RFC 6749, section 4.1.2).
+            if let Some(family_id) = grant.family_id {
+                transaction.rollback().await?;
+                tracing::warn!(
+                    "authorization code reused, revoking tokens issued to {}",
+                    grant.user_id
+                );
+                ctx.refresh_tokens.revoke_family(&family_id).await?;
+                return Err(invalid_grant());
+            }
+            let family_id = RefreshTokenStore::new_family_id();
+            query!(
+                "UPDATE oauth2_codes SET family_id = $2 WHERE code_hash = $1",
+                hash(code),
+                family_id
+            )
+            .execute(&mut transaction)
+            .await?;
+            transaction.commit().await?;
+            if grant.expiry <= Utc::now()
+                || grant.client_id != client.client_id
+                || payload.redirect_uri.as_deref() != Some(grant.redirect_uri.as_str())
+            {
+                return Err(invalid_grant());
+            }
+            if let Some(code_challenge) = &grant.code_challenge {
+                let code_verifier = payload.code_verifier.as_deref().unwrap_or_default();
+                if !verify_code_challenge(code_challenge, code_verifier) {
+                    return Err(invalid_grant());
+                }
+            }
+            issue_tokens(
+                &ctx,
+                grant.user_id,
+                &client.client_id,
+                grant.scope,
+                Some(family_id),
+            )
+            .await?
+        }
+        "client_credentials" => {
+            if !client.is_confidential {
+                return Err(TokenError::OAuth2(
+                    StatusCode::BAD_REQUEST,
+                    "unauthorized_client",
+                    "public clients cannot use the client credentials grant",
+                ));
+            }
+            // A client acting on its own behalf acts as the user who registered it,
+            // limited to the scope that user granted it at registration.
+            let scope = narrow_scope(payload.scope.as_deref(), &client.scope)?;
+            issue_tokens(&ctx, client.owner, &client.client_id, scope, None).await?
+        }
+        "refresh_token" => {
+            let refresh_token = payload.refresh_token.as_deref().ok_or_else(invalid_grant)?;
+            let grant = ctx
+                .refresh_tokens
+                .redeem(refresh_token, Some(&client.client_id))
+                .await?
+                .ok_or_else(invalid_grant)?;
+            let scope = narrow_scope(
+                payload.scope.as_deref(),
+                grant.scope.as_deref().unwrap_or_default(),
+            )?;
+            issue_tokens(
+                &ctx,
+                grant.username,
+                &client.client_id,
+                scope,
+                Some(grant.family_id),
+            )
+            .await?
+        }
+        _ => {
+            return Err(TokenError::OAuth2(
+                StatusCode::BAD_REQUEST,
+                "unsupported_grant_type",
+                "the grant type is not supported",
+            ))
+        }
+    };
+    Ok(Json(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middlewares::{
+        authenticate,
+        tests::{basic_request, context, create_user, delete_user, random_name, with_json},
+    };
+    use axum::body::Body;
+    use http::{header::CONTENT_TYPE, Method, Request};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    const REDIRECT_URI: &str = "https://client.example/callback";
+    const VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+
+    fn app(ctx: &ApiContext) -> Router {
+        Router::new()
+            .nest("/oauth2", router())
+            .layer(from_fn(authenticate))
+            .layer(Extension(ctx.clone()))
+    }
+
+    async fn create_client(ctx: &ApiContext, owner: &str) -> String {
+        let client_id = random_name();
+        query!(
+            "INSERT INTO oauth2_clients (client_id, name, owner, redirect_uris, scope) VALUES ($1, $1, $2, $3, $4)",
+            client_id,
+            owner,
+            REDIRECT_URI,
+            SCOPES.join(" ")
+        )
+        .execute(&ctx.db)
+        .await
+        .unwrap();
+        client_id
+    }
+
+    async fn create_code(ctx: &ApiContext, client_id: &str, username: &str) -> String {
+        let code = random_id();
+        query!(
+            "INSERT INTO oauth2_codes (code_hash, client_id, user_id, redirect_uri, scope, code_challenge, expiry) VALUES ($1, $2, $3, $4, 'read_message', $5, $6)",
+            hash(&code),
+            client_id,
+            username,
+            REDIRECT_URI,
+            hash(VERIFIER),
+            Utc::now() + Duration::minutes(AUTHORIZATION_CODE_EXPIRY_MINUTES)
+        )
+        .execute(&ctx.db)
+        .await
+        .unwrap();
+        code
+    }
+
+    async fn exchange(
+        ctx: &ApiContext,
+        client_id: &str,
+        code: &str,
+        redirect_uri: &str,
+    ) -> (StatusCode, Value) {
+        let mut form = Url::parse("https://localhost/").unwrap();
+        form.query_pairs_mut()
+            .append_pair("grant_type", "authorization_code")
+            .append_pair("client_id", client_id)
+            .append_pair("code", code)
+            .append_pair("redirect_uri", redirect_uri)
+            .append_pair("code_verifier", VERIFIER);
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(OAUTH2_TOKEN_PATH)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(form.query().unwrap().to_string()))
+            .unwrap();
+        let response = app(ctx).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn code_challenge_is_verified_with_s256() {
+        // The example from RFC 7636, appendix B.
+        let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+        assert_eq!(hash(VERIFIER), challenge);
+        assert!(verify_code_challenge(challenge, VERIFIER));
+        assert!(!verify_code_challenge(
+            challenge,
+            &VERIFIER.replace('d', "e")
+        ));
+        // A plain challenge is just the verifier, which never matches its hash.
+        assert!(!verify_code_challenge(VERIFIER, VERIFIER));
+    }
+
+    #[test]
+    fn malformed_code_verifiers_are_rejected() {
+        let short = &VERIFIER[..42];
+        assert!(!verify_code_challenge(&hash(short), short));
+        let invalid = format!("{}+", &VERIFIER[..42]);
+        assert!(!verify_code_challenge(&hash(&invalid), &invalid));
+    }
+
+    #[tokio::test]
+    async fn plain_code_challenges_are_refused() {
+        let ctx = context().await;
+        let username = create_user(&ctx).await;
+        let client_id = create_client(&ctx, &username).await;
+        let request = with_json(
+            basic_request(Method::POST, "/oauth2/authorize", &username),
+            json!({
+                "response_type": "code",
+                "client_id": client_id,
+                "redirect_uri": REDIRECT_URI,
+                "code_challenge": VERIFIER,
+                "code_challenge_method": "plain",
+            }),
+        );
+        let response = app(&ctx).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        delete_user(&ctx, &username).await;
+    }
+
+    #[tokio::test]
+    async fn code_is_bound_to_its_redirect_uri() {
+        let ctx = context().await;
+        let username = create_user(&ctx).await;
+        let client_id = create_client(&ctx, &username).await;
+        let code = create_code(&ctx, &client_id, &username).await;
+        let (status, body) =
+            exchange(&ctx, &client_id, &code, "https://client.example/other").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "invalid_grant");
+        delete_user(&ctx, &username).await;
+    }
+
+    #[tokio::test]
+    async fn replayed_code_revokes_the_tokens_issued_from_it() {
+        let ctx = context().await;
+        let username = create_user(&ctx).await;
+        let client_id = create_client(&ctx, &username).await;
+        let code = create_code(&ctx, &client_id, &username).await;
+        let (status, body) = exchange(&ctx, &client_id, &code, REDIRECT_URI).await;
+        assert_eq!(status, StatusCode::OK);
+        let access_token = body["access_token"].as_str().unwrap();
+        let refresh_token = body["refresh_token"].as_str().unwrap();
+        assert!(ctx.tokens.read(access_token).await.unwrap().is_some());
+        let (status, body) = exchange(&ctx, &client_id, &code, REDIRECT_URI).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "invalid_grant");
+        assert!(ctx.tokens.read(access_token).await.unwrap().is_none());
+        let grant = ctx
+            .refresh_tokens
+            .redeem(refresh_token, Some(&client_id))
+            .await
+            .unwrap();
+        assert!(grant.is_none());
+        delete_user(&ctx, &username).await;
+    }
+}
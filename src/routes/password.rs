@@ -0,0 +1,249 @@
+use crate::api::{ApiContext, AuthContext, Json, Path, Scope};
+use crate::error::ApiError;
+use crate::lockout::LoginAttempt;
+use crate::middlewares::{require_authentication, require_scope};
+use crate::routes::{check_subject, USER_REGEX};
+use crate::tokens::{hash, invalidate_user_tokens, random_id};
+use axum::{
+    extract::{ConnectInfo, OriginalUri},
+    handler::Handler,
+    middleware::from_fn,
+    routing::{post, put},
+    Extension, Router,
+};
+use chrono::Utc;
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_scalar};
+use std::net::SocketAddr;
+
+pub fn router() -> Router {
+    let change_password = change_password
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_account")))
+        .layer(from_fn(require_authentication));
+    Router::new()
+        .route("/:user_id/password", put(change_password))
+        .route("/password-reset", post(request_password_reset))
+        .route("/password-reset/confirm", post(confirm_password_reset))
+}
+
+fn invalid_reset_token() -> ApiError {
+    ApiError::BadRequest("invalid or expired reset token".to_string())
+}
+
+async fn update_password(
+    ctx: &ApiContext,
+    username: &str,
+    password: &str,
+    reset_token: Option<&str>,
+) -> Result<(), ApiError> {
+    let pw_hash = ctx.password_hashing.hash(password).await?;
+    let mut transaction = ctx.db.begin().await?;
+    if let Some(reset_token) = reset_token {
+        // Consuming the token in the same transaction keeps it single-use when
+        // two confirmations race each other.
+        let result = query!(
+            "DELETE FROM password_reset_tokens WHERE token_hash = $1 AND user_id = $2 AND expiry > now()",
+            hash(reset_token),
+            username
+        )
+        .execute(&mut transaction)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(invalid_reset_token());
+        }
+    }
+    query!(
+        "UPDATE users SET pw_hash = $2 WHERE user_id = $1",
+        username,
+        pw_hash
+    )
+    .execute(&mut transaction)
+    .await?;
+    query!(
+        "DELETE FROM password_reset_tokens WHERE user_id = $1",
+        username
+    )
+    .execute(&mut transaction)
+    .await?;
+    let session_ids = invalidate_user_tokens(&mut transaction, username).await?;
+    transaction.commit().await?;
+    ctx.tokens.forget_sessions(&session_ids).await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct ChangePasswordPayload {
+    current_password: String,
+    new_password: String,
+}
+
+#[derive(Serialize)]
+struct ChangePasswordBody {}
+
+async fn change_password(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    OriginalUri(uri): OriginalUri,
+    Path(user_id): Path<String>,
+    Json(payload): Json<ChangePasswordPayload>,
+) -> Result<Json<ChangePasswordBody>, ApiError> {
+    check_subject(&auth_ctx, &user_id)?;
+    ctx.password_policy.check(&user_id, &payload.new_password)?;
+    let attempt = LoginAttempt {
+        username: &user_id,
+        ip: Some(addr.ip()),
+        method: "PUT",
+        path: uri.path(),
+    };
+    ctx.login_throttle.check(&attempt).await?;
+    let pw_hash = query_scalar!("SELECT pw_hash FROM users WHERE user_id = $1", user_id)
+        .fetch_optional(&ctx.db)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    if !ctx
+        .password_hashing
+        .verify(&pw_hash, &payload.current_password)
+        .await?
+    {
+        ctx.login_throttle.record_failure(&attempt).await?;
+        return Err(ApiError::BadRequest(
+            "current password is incorrect".to_string(),
+        ));
+    }
+    update_password(&ctx, &user_id, &payload.new_password, None).await?;
+    Ok(Json(ChangePasswordBody {}))
+}
+
+#[derive(Deserialize)]
+struct PasswordResetPayload {
+    username: String,
+}
+
+#[derive(Serialize)]
+struct PasswordResetBody {}
+
+async fn request_password_reset(
+    ctx: Extension<ApiContext>,
+    Json(payload): Json<PasswordResetPayload>,
+) -> Result<(StatusCode, Json<PasswordResetBody>), ApiError> {
+    let username = payload.username;
+    if !USER_REGEX.is_match(&username) {
+        return Err(ApiError::BadRequest("invalid user name".to_string()));
+    }
+    let exists = query_scalar!("SELECT user_id FROM users WHERE user_id = $1", username)
+        .fetch_optional(&ctx.db)
+        .await?
+        .is_some();
+    if exists {
+        let token = random_id();
+        let expiry = Utc::now() + ctx.password_reset_expiry;
+        let mut transaction = ctx.db.begin().await?;
+        query!(
+            "DELETE FROM password_reset_tokens WHERE user_id = $1",
+            username
+        )
+        .execute(&mut transaction)
+        .await?;
+        query!(
+            "INSERT INTO password_reset_tokens (token_hash, user_id, expiry) VALUES ($1, $2, $3)",
+            hash(&token),
+            username,
+            expiry
+        )
+        .execute(&mut transaction)
+        .await?;
+        transaction.commit().await?;
+        let message = format!(
+            "Use the following token to reset your password before {}: {}",
+            expiry, token
+        );
+        ctx.notifier
+            .send(&username, "Password reset", &message)
+            .await?;
+    }
+    Ok((StatusCode::ACCEPTED, Json(PasswordResetBody {})))
+}
+
+#[derive(Deserialize)]
+struct ConfirmPasswordResetPayload {
+    token: String,
+    new_password: String,
+}
+
+async fn confirm_password_reset(
+    ctx: Extension<ApiContext>,
+    Json(payload): Json<ConfirmPasswordResetPayload>,
+) -> Result<Json<PasswordResetBody>, ApiError> {
+    let user_id = query_scalar!(
+        "SELECT user_id FROM password_reset_tokens WHERE token_hash = $1 AND expiry > now()",
+        hash(&payload.token)
+    )
+    .fetch_optional(&ctx.db)
+    .await?
+    .ok_or_else(invalid_reset_token)?;
+    ctx.password_policy.check(&user_id, &payload.new_password)?;
+    update_password(&ctx, &user_id, &payload.new_password, Some(&payload.token)).await?;
+    Ok(Json(PasswordResetBody {}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middlewares::{
+        authenticate,
+        tests::{bearer_request, context, create_user, delete_user, with_json, PASSWORD},
+    };
+    use crate::tokens::{
+        hmac::{HmacKeys, HmacTokenStore},
+        Token, SCOPE_ATTRIBUTE,
+    };
+    use http::Method;
+    use serde_json::json;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    fn app(ctx: &ApiContext) -> Router {
+        Router::new()
+            .nest("/users", router())
+            .layer(from_fn(authenticate))
+            .layer(Extension(ctx.clone()))
+    }
+
+    #[tokio::test]
+    async fn changing_password_invalidates_self_contained_tokens() {
+        let keys = HmacKeys::new(vec![("k1".to_string(), vec![7u8; 32])]).unwrap();
+        let ctx = ApiContext {
+            tokens: Arc::new(HmacTokenStore::new(keys)),
+            ..context().await
+        };
+        let username = create_user(&ctx).await;
+        let mut token = Token::new(Utc::now() + ctx.token_expiry, username.clone());
+        token
+            .attributes
+            .insert(SCOPE_ATTRIBUTE.to_string(), "manage_account".to_string());
+        let token_id = ctx.tokens.create(&token).await.unwrap();
+        let uri = format!("/users/{}/password", username);
+        let change = |token_id: &str, current_password: &str, new_password: &str| {
+            with_json(
+                bearer_request(Method::PUT, &uri, token_id),
+                json!({"current_password": current_password, "new_password": new_password}),
+            )
+        };
+        let response = app(&ctx)
+            .oneshot(change(&token_id, PASSWORD, "battery-staple-horse"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "{}");
+        let response = app(&ctx)
+            .oneshot(change(&token_id, "battery-staple-horse", PASSWORD))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        delete_user(&ctx, &username).await;
+    }
+}
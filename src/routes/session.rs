@@ -0,0 +1,404 @@
+use crate::api::{ApiContext, AuthContext, Json, Path, Query, Scope, SCOPES};
+use crate::error::ApiError;
+use crate::lockout::LoginAttempt;
+use crate::mfa;
+use crate::middlewares::{require_authentication, require_scope};
+use crate::tokens::{
+    csrf_token,
+    refresh::{RefreshToken, RefreshTokenStore},
+    Token, AUTH_TIME_ATTRIBUTE, MFA_ATTRIBUTE, SCOPE_ATTRIBUTE, SESSION_COOKIE,
+    USER_AGENT_ATTRIBUTE,
+};
+use axum::{
+    extract::{ConnectInfo, OriginalUri, TypedHeader},
+    handler::Handler,
+    headers::{Cookie, UserAgent},
+    middleware::from_fn,
+    response::{IntoResponse, Response},
+    routing::{delete, post},
+    Extension, Router,
+};
+use chrono::{DateTime, Utc};
+use http::{header::SET_COOKIE, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, net::SocketAddr};
+
+pub const SESSIONS_PATH: &str = "/sessions";
+
+pub fn router() -> Router {
+    let create_session = create_session.layer(from_fn(require_authentication));
+    let list_sessions = list_sessions
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("list_sessions")))
+        .layer(from_fn(require_authentication));
+    // Any token may log itself out, but revoking other sessions needs a scope.
+    let delete_current_session = delete_current_session.layer(from_fn(require_authentication));
+    let delete_session = delete_session
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_sessions")))
+        .layer(from_fn(require_authentication));
+    Router::new()
+        .route(
+            "/",
+            post(create_session)
+                .get(list_sessions)
+                .delete(delete_current_session),
+        )
+        .route("/refresh", post(refresh_session))
+        .route("/:session_id", delete(delete_session))
+}
+
+#[derive(Deserialize)]
+struct CreateSessionParam {
+    #[serde(default)]
+    cookie: bool,
+    scope: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CreateSessionPayload {
+    otp: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CreateSessionBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    csrf_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
+    expires: DateTime<Utc>,
+}
+
+async fn issue_token(
+    ctx: &ApiContext,
+    username: String,
+    scope: Option<&str>,
+    attributes: &HashMap<String, String>,
+    user_agent: Option<TypedHeader<UserAgent>>,
+    max_expiry: Option<DateTime<Utc>>,
+) -> Result<(String, DateTime<Utc>), ApiError> {
+    let expires = Utc::now() + ctx.token_expiry;
+    let expires = max_expiry.map_or(expires, |max_expiry| expires.min(max_expiry));
+    let mut token = Token::new(expires, username);
+    token.attributes.extend(attributes.clone());
+    if let Some(TypedHeader(user_agent)) = user_agent {
+        token
+            .attributes
+            .insert(USER_AGENT_ATTRIBUTE.to_string(), user_agent.to_string());
+    }
+    if let Some(scope) = scope {
+        token
+            .attributes
+            .insert(SCOPE_ATTRIBUTE.to_string(), scope.to_string());
+    }
+    let token = ctx.tokens.create(&token).await?;
+    Ok((token, expires))
+}
+
+async fn issue_refresh_token(
+    ctx: &ApiContext,
+    username: String,
+    scope: Option<String>,
+    attributes: HashMap<String, String>,
+    family_id: String,
+    token: &str,
+) -> Result<String, ApiError> {
+    let refresh_token = RefreshToken {
+        expiry: Utc::now() + ctx.refresh_token_expiry,
+        username,
+        client_id: None,
+        scope,
+        family_id,
+        session_id: ctx.tokens.session_id(token),
+        attributes,
+    };
+    ctx.refresh_tokens.create(&refresh_token).await
+}
+
+async fn create_session(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    user_agent: Option<TypedHeader<UserAgent>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    OriginalUri(uri): OriginalUri,
+    Query(param): Query<CreateSessionParam>,
+    payload: Option<Json<CreateSessionPayload>>,
+) -> Result<Response, ApiError> {
+    let username = auth_ctx
+        .subject
+        .clone()
+        .ok_or(ApiError::AuthenticationRequired)?;
+    let mfa = if auth_ctx.mfa {
+        true
+    } else if mfa::is_enrolled(&ctx.db, &username).await? {
+        let otp = payload
+            .and_then(|Json(payload)| payload.otp)
+            .ok_or(ApiError::MfaRequired)?;
+        let attempt = LoginAttempt {
+            username: &username,
+            ip: Some(addr.ip()),
+            method: "POST",
+            path: uri.path(),
+        };
+        ctx.login_throttle.check(&attempt).await?;
+        if !mfa::verify_second_factor(&ctx.db, &username, &otp).await? {
+            ctx.login_throttle.record_failure(&attempt).await?;
+            return Err(ApiError::MfaRequired);
+        }
+        true
+    } else {
+        false
+    };
+    let mut attributes = HashMap::new();
+    if mfa {
+        attributes.insert(MFA_ATTRIBUTE.to_string(), true.to_string());
+    }
+    if let Some(auth_time) = auth_ctx.auth_time {
+        attributes.insert(
+            AUTH_TIME_ATTRIBUTE.to_string(),
+            auth_time.timestamp().to_string(),
+        );
+    }
+    let scope = match param.scope {
+        Some(scope) => {
+            let scopes: Vec<&str> = scope.split_whitespace().collect();
+            if let Some(unknown) = scopes.iter().find(|s| !SCOPES.contains(s)) {
+                return Err(ApiError::BadRequest(format!("unknown scope: {}", unknown)));
+            }
+            if let Some(exceeded) = scopes.iter().find(|s| !auth_ctx.has_scope(s)) {
+                return Err(ApiError::BadRequest(format!(
+                    "scope exceeds current token: {}",
+                    exceeded
+                )));
+            }
+            Some(scopes.join(" "))
+        }
+        None => auth_ctx.scope.as_ref().map(|scopes| scopes.join(" ")),
+    };
+    // A token may be exchanged for a narrower one, but not for one that outlives
+    // it or can be refreshed, or a stolen token could be renewed indefinitely.
+    let is_token_exchange = auth_ctx.token.is_some();
+    let (token, expires) = issue_token(
+        &ctx,
+        username.clone(),
+        scope.as_deref(),
+        &attributes,
+        user_agent,
+        auth_ctx.expiry,
+    )
+    .await?;
+    if !param.cookie {
+        let refresh_token = if is_token_exchange {
+            None
+        } else {
+            let family_id = RefreshTokenStore::new_family_id();
+            Some(
+                issue_refresh_token(&ctx, username, scope.clone(), attributes, family_id, &token)
+                    .await?,
+            )
+        };
+        let body = CreateSessionBody {
+            token: Some(token),
+            refresh_token,
+            csrf_token: None,
+            scope,
+            expires,
+        };
+        return Ok((StatusCode::CREATED, Json(body)).into_response());
+    }
+    let cookie = format!(
+        "{}={}; Path=/; Max-Age={}; Secure; HttpOnly; SameSite=Strict",
+        SESSION_COOKIE,
+        token,
+        (expires - Utc::now()).num_seconds()
+    );
+    let body = CreateSessionBody {
+        token: None,
+        refresh_token: None,
+        csrf_token: Some(csrf_token(&token)),
+        scope,
+        expires,
+    };
+    Ok((StatusCode::CREATED, [(SET_COOKIE, cookie)], Json(body)).into_response())
+}
+
+#[derive(Deserialize)]
+struct RefreshSessionPayload {
+    refresh_token: String,
+}
+
+async fn refresh_session(
+    ctx: Extension<ApiContext>,
+    user_agent: Option<TypedHeader<UserAgent>>,
+    Json(payload): Json<RefreshSessionPayload>,
+) -> Result<Json<CreateSessionBody>, ApiError> {
+    let grant = ctx
+        .refresh_tokens
+        .redeem(&payload.refresh_token, None)
+        .await?
+        .ok_or(ApiError::AuthenticationRequired)?;
+    let (token, expires) = issue_token(
+        &ctx,
+        grant.username.clone(),
+        grant.scope.as_deref(),
+        &grant.attributes,
+        user_agent,
+        None,
+    )
+    .await?;
+    let refresh_token = issue_refresh_token(
+        &ctx,
+        grant.username,
+        grant.scope.clone(),
+        grant.attributes,
+        grant.family_id,
+        &token,
+    )
+    .await?;
+    Ok(Json(CreateSessionBody {
+        token: Some(token),
+        refresh_token: Some(refresh_token),
+        csrf_token: None,
+        scope: grant.scope,
+        expires,
+    }))
+}
+
+#[derive(Serialize)]
+struct SessionBody {
+    id: String,
+    created: DateTime<Utc>,
+    last_used: Option<DateTime<Utc>>,
+    expires: DateTime<Utc>,
+    user_agent: Option<String>,
+    current: bool,
+}
+
+async fn list_sessions(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+) -> Result<Json<Vec<SessionBody>>, ApiError> {
+    let username = auth_ctx
+        .subject
+        .as_deref()
+        .ok_or(ApiError::AuthenticationRequired)?;
+    let current_id = auth_ctx
+        .token
+        .as_deref()
+        .and_then(|token| ctx.tokens.session_id(token));
+    let sessions = ctx
+        .tokens
+        .list_sessions(username)
+        .await?
+        .into_iter()
+        .map(|session| SessionBody {
+            current: current_id.as_ref() == Some(&session.id),
+            id: session.id,
+            created: session.created,
+            last_used: session.last_used,
+            expires: session.expiry,
+            user_agent: session.user_agent,
+        })
+        .collect();
+    Ok(Json(sessions))
+}
+
+#[derive(Serialize)]
+struct DeleteSessionBody {}
+
+async fn delete_current_session(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    cookie: Option<TypedHeader<Cookie>>,
+) -> Result<Response, ApiError> {
+    let token = auth_ctx.token.as_deref().ok_or_else(|| {
+        ApiError::BadRequest("request is not authenticated with a session token".to_string())
+    })?;
+    if let Some(session_id) = ctx.tokens.session_id(token) {
+        ctx.refresh_tokens.revoke_session(&session_id).await?;
+    }
+    ctx.tokens.revoke(token).await?;
+    let is_cookie_session =
+        cookie.is_some_and(|TypedHeader(cookie)| cookie.get(SESSION_COOKIE) == Some(token));
+    if is_cookie_session {
+        let cookie = format!(
+            "{}=; Path=/; Max-Age=0; Secure; HttpOnly; SameSite=Strict",
+            SESSION_COOKIE
+        );
+        return Ok(([(SET_COOKIE, cookie)], Json(DeleteSessionBody {})).into_response());
+    }
+    Ok(Json(DeleteSessionBody {}).into_response())
+}
+
+async fn delete_session(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    Path(session_id): Path<String>,
+) -> Result<Json<DeleteSessionBody>, ApiError> {
+    let username = auth_ctx
+        .subject
+        .as_deref()
+        .ok_or(ApiError::AuthenticationRequired)?;
+    if !ctx.tokens.revoke_session(username, &session_id).await? {
+        return Err(ApiError::NotFound);
+    }
+    ctx.refresh_tokens.revoke_session(&session_id).await?;
+    Ok(Json(DeleteSessionBody {}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middlewares::{
+        authenticate,
+        tests::{basic_request, bearer_request, context, create_user, delete_user},
+    };
+    use http::Method;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    fn app(ctx: &ApiContext) -> Router {
+        Router::new()
+            .nest(SESSIONS_PATH, router())
+            .layer(from_fn(authenticate))
+            .layer(Extension(ctx.clone()))
+    }
+
+    async fn json_body(response: Response) -> Value {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn expires(body: &Value) -> DateTime<Utc> {
+        body["expires"].as_str().unwrap().parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn exchanged_token_cannot_outlive_or_refresh_its_parent() {
+        let ctx = context().await;
+        let username = create_user(&ctx).await;
+        let response = app(&ctx)
+            .oneshot(basic_request(Method::POST, SESSIONS_PATH, &username))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let session = json_body(response).await;
+        assert!(session["refresh_token"].is_string());
+        let uri = format!("{}?scope=read_message", SESSIONS_PATH);
+        let token = session["token"].as_str().unwrap();
+        let response = app(&ctx)
+            .oneshot(bearer_request(Method::POST, &uri, token))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let exchanged = json_body(response).await;
+        assert_eq!(exchanged["scope"], "read_message");
+        assert!(exchanged.get("refresh_token").is_none());
+        assert!(expires(&exchanged) <= expires(&session));
+        delete_user(&ctx, &username).await;
+    }
+}
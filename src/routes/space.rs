@@ -1,5 +1,6 @@
-use crate::api::{ApiContext, CreatedJson, Json, Query, Path, AuthContext, Permission};
+use crate::api::{Action, ApiContext, CreatedJson, Json, Query, Path, AuthContext, Permission, Scope};
 use crate::error::ApiError;
+use crate::capability;
 use axum::{
     extract::OriginalUri,
     routing::{get, post},
@@ -12,16 +13,34 @@ use sqlx::{query, query_scalar};
 use chrono::{DateTime, Duration, Utc};
 use validator::Validate;
 use crate::routes::USER_REGEX;
-use crate::middlewares::{require_permission, require_authentication};
+use crate::middlewares::{enforce_policy, require_capability, require_authentication, require_scope};
+
+const OWNER_ROLE: &str = "owner";
 
 pub fn router() -> Router {
-    let create_space = create_space.layer(from_fn(require_authentication));
-    let post_message = post_message.layer(from_fn(require_permission))
-    .layer(Extension(Permission { read: false, write: true, delete: false, }));
-    let find_messages = find_messages.layer(from_fn(require_permission))
-    .layer(Extension(Permission { read: true, write: false, delete: false, }));
-    let read_message = read_message.layer(from_fn(require_permission))
-    .layer(Extension(Permission { read: true, write: false, delete: false, }));
+    let create_space = create_space.layer(from_fn(enforce_policy))
+    .layer(Extension(Action("create_space")))
+    .layer(from_fn(require_scope))
+    .layer(Extension(Scope("create_space")))
+    .layer(from_fn(require_authentication));
+    let post_message = post_message.layer(from_fn(enforce_policy))
+    .layer(Extension(Action("post_message")))
+    .layer(from_fn(require_scope))
+    .layer(Extension(Scope("post_message")))
+    .layer(from_fn(require_capability))
+    .layer(Extension(Permission { read: false, write: true, delete: false, admin: false, }));
+    let find_messages = find_messages.layer(from_fn(enforce_policy))
+    .layer(Extension(Action("list_messages")))
+    .layer(from_fn(require_scope))
+    .layer(Extension(Scope("list_messages")))
+    .layer(from_fn(require_capability))
+    .layer(Extension(Permission { read: true, write: false, delete: false, admin: false, }));
+    let read_message = read_message.layer(from_fn(enforce_policy))
+    .layer(Extension(Action("read_message")))
+    .layer(from_fn(require_scope))
+    .layer(Extension(Scope("read_message")))
+    .layer(from_fn(require_capability))
+    .layer(Extension(Permission { read: true, write: false, delete: false, admin: false, }));
     Router::new().route("/", post(create_space)).nest(
         "/:space_id/messages",
         Router::new()
@@ -42,6 +61,16 @@ struct CreateSpacePayload {
 struct CreateSpaceBody {
     name: String,
     uri: String,
+    links: CapabilityLinks,
+}
+
+#[derive(Serialize)]
+struct CapabilityLinks {
+    read: String,
+    write: String,
+    // Deleting messages also requires a recent login, so this link only works
+    // for a holder who is authenticated as well.
+    delete: String,
 }
 
 async fn create_space(
@@ -75,13 +104,23 @@ async fn create_space(
     )
     .fetch_one(&mut transaction)
     .await?;
-    query!("INSERT INTO permissions (space_id, user_id, perms) VALUES ($1, $2, $3)", space_id, owner, "rwd").execute(&mut transaction).await?;
+    query!("INSERT INTO permissions (space_id, user_id, role) VALUES ($1, $2, $3)", space_id, owner, OWNER_ROLE).execute(&mut transaction).await?;
+    let expiry = Utc::now() + ctx.capability_expiry;
+    let read_token = capability::create(&mut transaction, space_id, "r", &owner, expiry).await?;
+    let write_token = capability::create(&mut transaction, space_id, "rw", &owner, expiry).await?;
+    let delete_token = capability::create(&mut transaction, space_id, "rwd", &owner, expiry).await?;
     transaction.commit().await?;
     let uri = format!("{}/{}", uri, space_id);
+    let links = CapabilityLinks {
+        read: capability::capability_uri(&uri, &read_token),
+        write: capability::capability_uri(&uri, &write_token),
+        delete: capability::capability_uri(&uri, &delete_token),
+    };
     Ok(
         CreatedJson(uri.clone(), CreateSpaceBody {
             name,
             uri,
+            links,
         }),
     )
 }
@@ -89,7 +128,7 @@ async fn create_space(
 #[derive(Deserialize, Validate)]
 struct PostMessagePayload {
     #[validate(regex = "USER_REGEX")]
-    author: String,
+    author: Option<String>,
     #[validate(length(max = 1024))]
     message: String,
 }
@@ -114,15 +153,17 @@ async fn post_message(
             return Err(ApiError::BadRequest("message too long".to_string()));
         }
     }
-    let author = payload.author;
     let message = payload.message;
-    let is_author_match = match &auth_ctx.subject {
-        None => false,
-        Some(subject) => *subject == author,
+    let author = match (&auth_ctx.subject, payload.author) {
+        (Some(subject), Some(author)) if *subject == author => author,
+        (Some(_), _) => {
+            return Err(ApiError::BadRequest("author must match authenticated user".to_string()));
+        }
+        (None, None) => capability::ANONYMOUS_AUTHOR.to_string(),
+        (None, Some(_)) => {
+            return Err(ApiError::BadRequest("anonymous messages cannot name an author".to_string()));
+        }
     };
-    if !is_author_match {
-        return Err(ApiError::BadRequest("author must match authenticated user".to_string()));
-    }
     let msg_id = query_scalar!(
         "INSERT INTO messages (space_id, author, msg_text) VALUES ($1, $2, $3) RETURNING msg_id",
         space_id,
@@ -131,7 +172,11 @@ async fn post_message(
     )
     .fetch_one(&ctx.db)
     .await?;
-    let uri = format!("{}/{}", uri, msg_id);
+    let uri = uri.to_string();
+    let uri = match uri.split_once('?') {
+        Some((path, query)) => format!("{}/{}?{}", path, msg_id, query),
+        None => format!("{}/{}", uri, msg_id),
+    };
     Ok(
         CreatedJson(uri.clone(), PostMessageBody {
             uri,
@@ -191,3 +236,95 @@ async fn find_messages(
     .await?;
     Ok(Json(result))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middlewares::{
+        authenticate,
+        tests::{basic_request, context, create_user, delete_user, with_json},
+    };
+    use axum::body::Body;
+    use http::{Method, Request, StatusCode};
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    fn app(ctx: &ApiContext) -> Router {
+        Router::new()
+            .nest("/spaces", router())
+            .layer(from_fn(authenticate))
+            .layer(Extension(ctx.clone()))
+    }
+
+    fn anonymous_request(method: Method, uri: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn create_space(ctx: &ApiContext, owner: &str) -> Value {
+        let request = with_json(
+            basic_request(Method::POST, "/spaces", owner),
+            json!({ "name": "test space", "owner": owner }),
+        );
+        let response = app(ctx).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn messages_uri(link: &Value) -> String {
+        let (space_uri, access_token) = link.as_str().unwrap().split_once('?').unwrap();
+        format!("{}/messages?{}", space_uri, access_token)
+    }
+
+    async fn post_message(ctx: &ApiContext, link: &Value) -> StatusCode {
+        let request = with_json(
+            anonymous_request(Method::POST, &messages_uri(link)),
+            json!({ "message": "hello" }),
+        );
+        app(ctx).oneshot(request).await.unwrap().status()
+    }
+
+    async fn list_messages(ctx: &ApiContext, link: &Value) -> StatusCode {
+        let request = anonymous_request(Method::GET, &messages_uri(link));
+        app(ctx).oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn capabilities_are_limited_to_their_permissions() {
+        let ctx = context().await;
+        let owner = create_user(&ctx).await;
+        let space = create_space(&ctx, &owner).await;
+        let links = &space["links"];
+        assert_eq!(list_messages(&ctx, &links["read"]).await, StatusCode::OK);
+        assert_eq!(post_message(&ctx, &links["read"]).await, StatusCode::FORBIDDEN);
+        assert_eq!(post_message(&ctx, &links["write"]).await, StatusCode::CREATED);
+        delete_user(&ctx, &owner).await;
+    }
+
+    #[tokio::test]
+    async fn capabilities_are_bounded_by_their_creator() {
+        let ctx = context().await;
+        let owner = create_user(&ctx).await;
+        let space = create_space(&ctx, &owner).await;
+        let links = &space["links"];
+        query!(
+            "UPDATE permissions SET role = 'observer' WHERE user_id = $1",
+            owner
+        )
+        .execute(&ctx.db)
+        .await
+        .unwrap();
+        assert_eq!(post_message(&ctx, &links["write"]).await, StatusCode::FORBIDDEN);
+        assert_eq!(list_messages(&ctx, &links["write"]).await, StatusCode::OK);
+        query!("DELETE FROM permissions WHERE user_id = $1", owner)
+            .execute(&ctx.db)
+            .await
+            .unwrap();
+        assert_eq!(list_messages(&ctx, &links["read"]).await, StatusCode::FORBIDDEN);
+        delete_user(&ctx, &owner).await;
+    }
+}
@@ -1,26 +1,46 @@
-use crate::api::{ApiContext, CreatedJson, Json};
+use crate::api::{ApiContext, AuthContext, CreatedJson, Json, Path, Scope, StepUp};
 use crate::error::ApiError;
-use crate::routes::USER_REGEX;
-use anyhow::{anyhow, Context};
-use axum::{extract::OriginalUri, routing::post, Extension, Router};
-use scrypt::password_hash::PasswordHasher;
-use scrypt::{
-    password_hash::{rand_core::OsRng, SaltString},
-    Scrypt,
+use crate::middlewares::{require_authentication, require_scope, require_step_up};
+use crate::routes::{check_self_or_admin, USER_REGEX};
+use crate::tokens::invalidate_user_tokens;
+use anyhow::anyhow;
+use axum::{
+    extract::OriginalUri,
+    handler::Handler,
+    middleware::from_fn,
+    routing::{get, post},
+    Extension, Router,
 };
+use chrono::{DateTime, Duration, Utc};
+use reqwest::Url;
 use serde::{Deserialize, Serialize};
-use sqlx::query;
+use sqlx::{query, query_scalar};
 use validator::Validate;
 
+const DELETE_USER_MAX_AUTH_AGE_MINUTES: i64 = 5;
+
 pub fn router() -> Router {
-    Router::new().route("/", post(register_user))
+    let update_user = update_user
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_account")))
+        .layer(from_fn(require_authentication));
+    let delete_user = delete_user
+        .layer(from_fn(require_scope))
+        .layer(Extension(Scope("manage_account")))
+        .layer(from_fn(require_step_up))
+        .layer(Extension(StepUp::recent(Duration::minutes(
+            DELETE_USER_MAX_AUTH_AGE_MINUTES,
+        ))));
+    Router::new().route("/", post(register_user)).route(
+        "/:user_id",
+        get(get_user).patch(update_user).delete(delete_user),
+    )
 }
 
 #[derive(Deserialize, Validate)]
 struct RegisterUserPayload {
     #[validate(regex = "USER_REGEX")]
     username: String,
-    #[validate(length(min = 8))]
     password: String,
 }
 
@@ -38,20 +58,16 @@ async fn register_user(
         if e.errors().contains_key("username") {
             return Err(ApiError::BadRequest("invalid user name".to_string()));
         }
-        if e.errors().contains_key("password") {
-            return Err(ApiError::BadRequest(
-                "password must be at least 8 characters".to_string(),
-            ));
-        }
     }
+    ctx.password_policy
+        .check(&payload.username, &payload.password)?;
     let username = payload.username;
     let password = payload.password;
-    let hash = Scrypt
-        .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
-        .context("failed to hash password")?
-        .to_string();
+    let hash = ctx.password_hashing.hash(&password).await?;
+    // Names of deleted accounts stay reserved so that nobody inherits the
+    // spaces or messages that still refer to them.
     let result = query!(
-        "INSERT INTO users (user_id, pw_hash) VALUES ($1, $2)",
+        "INSERT INTO users (user_id, pw_hash) SELECT $1::VARCHAR, $2 WHERE NOT EXISTS (SELECT 1 FROM deleted_users WHERE user_id = $1)",
         username,
         hash
     )
@@ -69,6 +85,281 @@ async fn register_user(
             let body = RegisterUserBody { username };
             Ok(CreatedJson(uri, body))
         }
+        0 => Err(ApiError::Conflict("user name already exists".to_string())),
         _ => Err(ApiError::ServerError(anyhow!("failed to create user"))),
     }
 }
+
+#[derive(Serialize)]
+struct UserProfileBody {
+    username: String,
+    display_name: Option<String>,
+    created: DateTime<Utc>,
+    #[serde(flatten)]
+    details: Option<UserDetailsBody>,
+}
+
+#[derive(Serialize)]
+struct UserDetailsBody {
+    bio: Option<String>,
+    avatar_url: Option<String>,
+}
+
+async fn get_user(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    Path(user_id): Path<String>,
+) -> Result<Json<UserProfileBody>, ApiError> {
+    let record = query!(
+        "SELECT user_id, display_name, bio, avatar_url, created FROM users WHERE user_id = $1",
+        user_id
+    )
+    .fetch_optional(&ctx.db)
+    .await?
+    .ok_or(ApiError::NotFound)?;
+    // Anonymous callers only see the public part of the profile.
+    let details = auth_ctx.subject.is_some().then_some(UserDetailsBody {
+        bio: record.bio,
+        avatar_url: record.avatar_url,
+    });
+    Ok(Json(UserProfileBody {
+        username: record.user_id,
+        display_name: record.display_name,
+        created: record.created,
+        details,
+    }))
+}
+
+#[derive(Deserialize, Validate)]
+struct UpdateUserPayload {
+    #[validate(length(max = 100))]
+    display_name: Option<String>,
+    #[validate(length(max = 1024))]
+    bio: Option<String>,
+    #[validate(length(max = 2048))]
+    avatar_url: Option<String>,
+}
+
+async fn update_user(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    Path(user_id): Path<String>,
+    Json(payload): Json<UpdateUserPayload>,
+) -> Result<Json<UserProfileBody>, ApiError> {
+    check_self_or_admin(&ctx, &auth_ctx, &user_id).await?;
+    if let Err(e) = payload.validate() {
+        if let Some(field) = e.errors().keys().next() {
+            return Err(ApiError::BadRequest(format!("{} is too long", field)));
+        }
+    }
+    if let Some(avatar_url) = payload.avatar_url.as_deref().filter(|url| !url.is_empty()) {
+        let is_https = Url::parse(avatar_url).is_ok_and(|url| url.scheme() == "https");
+        if !is_https {
+            return Err(ApiError::BadRequest(
+                "avatar URL must be an https URL".to_string(),
+            ));
+        }
+    }
+    let record = query!(
+        "UPDATE users SET display_name = CASE WHEN $2::text IS NULL THEN display_name ELSE NULLIF($2, '') END, bio = CASE WHEN $3::text IS NULL THEN bio ELSE NULLIF($3, '') END, avatar_url = CASE WHEN $4::text IS NULL THEN avatar_url ELSE NULLIF($4, '') END WHERE user_id = $1 RETURNING user_id, display_name, bio, avatar_url, created",
+        user_id,
+        payload.display_name,
+        payload.bio,
+        payload.avatar_url
+    )
+    .fetch_optional(&ctx.db)
+    .await?
+    .ok_or(ApiError::NotFound)?;
+    Ok(Json(UserProfileBody {
+        username: record.user_id,
+        display_name: record.display_name,
+        created: record.created,
+        details: Some(UserDetailsBody {
+            bio: record.bio,
+            avatar_url: record.avatar_url,
+        }),
+    }))
+}
+
+#[derive(Serialize)]
+struct DeleteUserBody {}
+
+async fn delete_user(
+    ctx: Extension<ApiContext>,
+    auth_ctx: Extension<AuthContext>,
+    Path(user_id): Path<String>,
+) -> Result<Json<DeleteUserBody>, ApiError> {
+    check_self_or_admin(&ctx, &auth_ctx, &user_id).await?;
+    let mut transaction = ctx.db.begin().await?;
+    // Deleting the spaces a user owns must not take other members' messages
+    // with them, so the owner has to hand them over or remove the members first.
+    let is_shared = query_scalar!(
+        r#"SELECT EXISTS(
+            SELECT 1 FROM permissions p JOIN spaces s ON s.space_id = p.space_id
+            WHERE s.owner = $1 AND p.user_id IS DISTINCT FROM $1
+        ) OR EXISTS(
+            SELECT 1 FROM messages m JOIN spaces s ON s.space_id = m.space_id
+            WHERE s.owner = $1 AND m.author <> $1
+        ) AS "is_shared!""#,
+        user_id
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+    if is_shared {
+        transaction.rollback().await?;
+        return Err(ApiError::Conflict(
+            "user owns spaces with other members".to_string(),
+        ));
+    }
+    let session_ids = invalidate_user_tokens(&mut transaction, &user_id).await?;
+    query!(
+        "DELETE FROM refresh_tokens WHERE client_id IN (SELECT client_id FROM oauth2_clients WHERE owner = $1)",
+        user_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    query!(
+        "DELETE FROM oauth2_codes WHERE user_id = $1 OR client_id IN (SELECT client_id FROM oauth2_clients WHERE owner = $1)",
+        user_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    query!("DELETE FROM oauth2_clients WHERE owner = $1", user_id)
+        .execute(&mut transaction)
+        .await?;
+    query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+        .execute(&mut transaction)
+        .await?;
+    query!("DELETE FROM user_mfa WHERE user_id = $1", user_id)
+        .execute(&mut transaction)
+        .await?;
+    query!(
+        "DELETE FROM password_reset_tokens WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    query!(
+        "DELETE FROM group_members WHERE user_id = $1 OR group_id IN (SELECT group_id FROM groups WHERE owner = $1) OR member_group_id IN (SELECT group_id FROM groups WHERE owner = $1)",
+        user_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    // Spaces the user owns would otherwise be left without an admin, so they
+    // are deleted along with the owner's own messages.
+    query!(
+        "DELETE FROM permissions WHERE user_id = $1 OR group_id IN (SELECT group_id FROM groups WHERE owner = $1) OR space_id IN (SELECT space_id FROM spaces WHERE owner = $1)",
+        user_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    query!("DELETE FROM groups WHERE owner = $1", user_id)
+        .execute(&mut transaction)
+        .await?;
+    query!(
+        "DELETE FROM capabilities WHERE created_by = $1 OR space_id IN (SELECT space_id FROM spaces WHERE owner = $1)",
+        user_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    query!(
+        "DELETE FROM messages WHERE author = $1 AND space_id IN (SELECT space_id FROM spaces WHERE owner = $1)",
+        user_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    query!("DELETE FROM spaces WHERE owner = $1", user_id)
+        .execute(&mut transaction)
+        .await?;
+    let result = query!("DELETE FROM users WHERE user_id = $1", user_id)
+        .execute(&mut transaction)
+        .await?;
+    if result.rows_affected() == 0 {
+        transaction.rollback().await?;
+        return Err(ApiError::NotFound);
+    }
+    query!("INSERT INTO deleted_users (user_id) VALUES ($1)", user_id)
+        .execute(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+    ctx.tokens.forget_sessions(&session_ids).await?;
+    Ok(Json(DeleteUserBody {}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middlewares::{
+        authenticate,
+        tests::{basic_request, bearer_request, context, create_user, delete_user},
+    };
+    use crate::tokens::{
+        hmac::{HmacKeys, HmacTokenStore},
+        Token,
+    };
+    use http::{Method, StatusCode};
+    use sqlx::query_scalar;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    fn app(ctx: &ApiContext) -> Router {
+        Router::new()
+            .nest("/users", router())
+            .layer(from_fn(authenticate))
+            .layer(Extension(ctx.clone()))
+    }
+
+    #[tokio::test]
+    async fn deleting_an_owner_of_shared_spaces_is_refused() {
+        let keys = HmacKeys::new(vec![("k1".to_string(), vec![7u8; 32])]).unwrap();
+        let ctx = ApiContext {
+            tokens: Arc::new(HmacTokenStore::new(keys)),
+            ..context().await
+        };
+        let owner = create_user(&ctx).await;
+        let member = create_user(&ctx).await;
+        let space_id = query_scalar!(
+            "INSERT INTO spaces (name, owner) VALUES ($1, $1) RETURNING space_id",
+            owner
+        )
+        .fetch_one(&ctx.db)
+        .await
+        .unwrap();
+        query!(
+            "INSERT INTO permissions (space_id, user_id, role) VALUES ($1, $2, 'member')",
+            space_id,
+            member
+        )
+        .execute(&ctx.db)
+        .await
+        .unwrap();
+        let token = Token::new(Utc::now() + ctx.token_expiry, owner.clone());
+        let token_id = ctx.tokens.create(&token).await.unwrap();
+        let uri = format!("/users/{}", owner);
+        let response = app(&ctx)
+            .oneshot(basic_request(Method::DELETE, &uri, &owner))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        query!(
+            "DELETE FROM permissions WHERE space_id = $1 AND user_id = $2",
+            space_id,
+            member
+        )
+        .execute(&ctx.db)
+        .await
+        .unwrap();
+        let response = app(&ctx)
+            .oneshot(basic_request(Method::DELETE, &uri, &owner))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app(&ctx)
+            .oneshot(bearer_request(Method::PATCH, &uri, &token_id))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        delete_user(&ctx, &member).await;
+        delete_user(&ctx, &owner).await;
+    }
+}
@@ -0,0 +1,19 @@
+use crate::api::{ApiContext, Json};
+use crate::tokens::jwt::Jwk;
+use axum::{routing::get, Extension, Router};
+use serde::Serialize;
+
+pub fn router() -> Router {
+    Router::new().route("/jwks.json", get(jwks))
+}
+
+#[derive(Serialize)]
+struct JwksBody {
+    keys: Vec<Jwk>,
+}
+
+async fn jwks(ctx: Extension<ApiContext>) -> Json<JwksBody> {
+    Json(JwksBody {
+        keys: ctx.tokens.public_keys(),
+    })
+}
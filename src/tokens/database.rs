@@ -0,0 +1,108 @@
+use super::{hash, random_id, Session, Token, TokenStore, USER_AGENT_ATTRIBUTE};
+use crate::error::ApiError;
+use anyhow::Context;
+use axum::async_trait;
+use sqlx::{query, PgPool};
+use std::collections::HashMap;
+
+#[derive(Clone)]
+pub struct DatabaseTokenStore {
+    db: PgPool,
+}
+
+impl DatabaseTokenStore {
+    pub fn new(db: PgPool) -> Self {
+        DatabaseTokenStore { db }
+    }
+
+    pub async fn delete_expired(&self) -> Result<u64, ApiError> {
+        let result = query!("DELETE FROM tokens WHERE expiry < now()")
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl TokenStore for DatabaseTokenStore {
+    async fn create(&self, token: &Token) -> Result<String, ApiError> {
+        let token_id = random_id();
+        let attributes = serde_json::to_string(&token.attributes)
+            .context("failed to serialize token attributes")?;
+        query!(
+            "INSERT INTO tokens (token_id, user_id, expiry, attributes) VALUES ($1, $2, $3, $4)",
+            hash(&token_id),
+            token.username,
+            token.expiry,
+            attributes
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(token_id)
+    }
+
+    async fn read(&self, token_id: &str) -> Result<Option<Token>, ApiError> {
+        let result = query!(
+            "UPDATE tokens SET last_used = now() WHERE token_id = $1 AND expiry > now() RETURNING user_id, expiry, attributes",
+            hash(token_id)
+        )
+        .fetch_optional(&self.db)
+        .await?;
+        match result {
+            Some(record) => {
+                let attributes = serde_json::from_str(&record.attributes)
+                    .context("failed to deserialize token attributes")?;
+                Ok(Some(Token {
+                    expiry: record.expiry,
+                    username: record.user_id,
+                    attributes,
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn revoke(&self, token_id: &str) -> Result<(), ApiError> {
+        query!("DELETE FROM tokens WHERE token_id = $1", hash(token_id))
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn list_sessions(&self, username: &str) -> Result<Vec<Session>, ApiError> {
+        let records = query!(
+            "SELECT token_id, created, last_used, expiry, attributes FROM tokens WHERE user_id = $1 AND expiry > now() ORDER BY created",
+            username
+        )
+        .fetch_all(&self.db)
+        .await?;
+        let mut sessions = Vec::with_capacity(records.len());
+        for record in records {
+            let attributes: HashMap<String, String> = serde_json::from_str(&record.attributes)
+                .context("failed to deserialize token attributes")?;
+            sessions.push(Session {
+                id: record.token_id,
+                created: record.created,
+                last_used: record.last_used,
+                expiry: record.expiry,
+                user_agent: attributes.get(USER_AGENT_ATTRIBUTE).cloned(),
+            });
+        }
+        Ok(sessions)
+    }
+
+    fn session_id(&self, token_id: &str) -> Option<String> {
+        Some(hash(token_id))
+    }
+
+    async fn revoke_session(&self, username: &str, session_id: &str) -> Result<bool, ApiError> {
+        let result = query!(
+            "DELETE FROM tokens WHERE token_id = $1 AND user_id = $2",
+            session_id,
+            username
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,169 @@
+use super::{Claims, Token, TokenStore};
+use crate::error::ApiError;
+use anyhow::{anyhow, Context};
+use axum::async_trait;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use rand::{rngs::OsRng, RngCore};
+
+const NONCE_LENGTH: usize = 24;
+
+pub struct EncryptedTokenStore {
+    keys: Vec<(String, XChaCha20Poly1305)>,
+}
+
+impl EncryptedTokenStore {
+    pub fn new(keys: Vec<(String, Vec<u8>)>) -> anyhow::Result<Self> {
+        if keys.is_empty() {
+            return Err(anyhow!("at least one encryption key must be configured"));
+        }
+        let keys = keys
+            .into_iter()
+            .map(|(key_id, key)| {
+                let cipher = XChaCha20Poly1305::new_from_slice(&key)
+                    .map_err(|_| anyhow!("key {} must be 32 bytes long", key_id))?;
+                Ok((key_id, cipher))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(EncryptedTokenStore { keys })
+    }
+
+    fn open(&self, token_id: &str) -> Option<Vec<u8>> {
+        let (key_id, sealed) = token_id.split_once('.')?;
+        let (_, cipher) = self.keys.iter().find(|(id, _)| id == key_id)?;
+        let sealed = base64::decode_config(sealed, base64::URL_SAFE_NO_PAD).ok()?;
+        if sealed.len() < NONCE_LENGTH {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+        cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: key_id.as_bytes(),
+                },
+            )
+            .ok()
+    }
+}
+
+#[async_trait]
+impl TokenStore for EncryptedTokenStore {
+    async fn create(&self, token: &Token) -> Result<String, ApiError> {
+        let (key_id, cipher) = &self.keys[0];
+        let plaintext =
+            serde_json::to_vec(&Claims::from(token)).context("failed to serialize token claims")?;
+        let mut nonce = [0u8; NONCE_LENGTH];
+        OsRng.fill_bytes(&mut nonce);
+        let ciphertext = cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &plaintext,
+                    aad: key_id.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("failed to encrypt token"))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Ok(format!(
+            "{}.{}",
+            key_id,
+            base64::encode_config(sealed, base64::URL_SAFE_NO_PAD)
+        ))
+    }
+
+    async fn read(&self, token_id: &str) -> Result<Option<Token>, ApiError> {
+        // Malformed tokens are treated like unknown ones, so that a bad cookie
+        // or header leaves the request anonymous rather than failing it.
+        let claims = self
+            .open(token_id)
+            .and_then(|plaintext| serde_json::from_slice::<Claims>(&plaintext).ok());
+        Ok(claims.and_then(Claims::into_token))
+    }
+
+    fn is_self_contained(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokens::{
+        tests::{tamper, test_token},
+        SCOPE_ATTRIBUTE,
+    };
+    use chrono::{Duration, Utc};
+
+    fn store() -> EncryptedTokenStore {
+        EncryptedTokenStore::new(vec![("k1".to_string(), vec![7u8; 32])]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn token_round_trips() {
+        let store = store();
+        let token_id = store
+            .create(&test_token(Utc::now() + Duration::minutes(10)))
+            .await
+            .unwrap();
+        let token = store.read(&token_id).await.unwrap().unwrap();
+        assert_eq!(token.username, "alice");
+        assert_eq!(
+            token.attributes.get(SCOPE_ATTRIBUTE).map(String::as_str),
+            Some("read_message")
+        );
+    }
+
+    #[tokio::test]
+    async fn tampered_token_is_rejected() {
+        let store = store();
+        let token_id = store
+            .create(&test_token(Utc::now() + Duration::minutes(10)))
+            .await
+            .unwrap();
+        assert!(store.read(&tamper(&token_id)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn expired_token_is_rejected() {
+        let store = store();
+        let token_id = store
+            .create(&test_token(Utc::now() - Duration::minutes(1)))
+            .await
+            .unwrap();
+        assert!(store.read(&token_id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn malformed_token_is_ignored() {
+        let store = store();
+        for token_id in ["", "no-key-id", "k1.", "k1.!!!", "k2.AAAA", "k1.AAAA"] {
+            assert!(store.read(token_id).await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn tokens_sealed_with_a_retired_key_stay_valid() {
+        let old = store();
+        let token_id = old
+            .create(&test_token(Utc::now() + Duration::minutes(10)))
+            .await
+            .unwrap();
+        let rotated = EncryptedTokenStore::new(vec![
+            ("k2".to_string(), vec![9u8; 32]),
+            ("k1".to_string(), vec![7u8; 32]),
+        ])
+        .unwrap();
+        assert!(rotated.read(&token_id).await.unwrap().is_some());
+        let new_token_id = rotated
+            .create(&test_token(Utc::now() + Duration::minutes(10)))
+            .await
+            .unwrap();
+        assert!(new_token_id.starts_with("k2."));
+        assert!(old.read(&new_token_id).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,153 @@
+use super::{Claims, Token, TokenStore};
+use crate::error::ApiError;
+use anyhow::{anyhow, Context};
+use axum::async_trait;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Clone)]
+pub struct HmacKeys {
+    keys: Vec<(String, Vec<u8>)>,
+}
+
+impl HmacKeys {
+    pub fn new(keys: Vec<(String, Vec<u8>)>) -> anyhow::Result<Self> {
+        if keys.is_empty() {
+            return Err(anyhow!("at least one HMAC key must be configured"));
+        }
+        Ok(HmacKeys { keys })
+    }
+
+    pub fn sign(&self, value: &str) -> Result<String, ApiError> {
+        let (key_id, key) = &self.keys[0];
+        let message = format!("{}.{}", key_id, value);
+        let mut mac = HmacSha256::new_from_slice(key).context("invalid HMAC key")?;
+        mac.update(message.as_bytes());
+        let tag = base64::encode_config(mac.finalize().into_bytes(), base64::URL_SAFE_NO_PAD);
+        Ok(format!("{}.{}", message, tag))
+    }
+
+    pub fn verify<'a>(&self, signed: &'a str) -> Option<&'a str> {
+        let (message, tag) = signed.rsplit_once('.')?;
+        let (key_id, value) = message.split_once('.')?;
+        let (_, key) = self.keys.iter().find(|(id, _)| id == key_id)?;
+        let tag = base64::decode_config(tag, base64::URL_SAFE_NO_PAD).ok()?;
+        let mut mac = HmacSha256::new_from_slice(key).ok()?;
+        mac.update(message.as_bytes());
+        mac.verify_slice(&tag).ok()?;
+        Some(value)
+    }
+}
+
+pub struct HmacTokenStore {
+    keys: HmacKeys,
+}
+
+impl HmacTokenStore {
+    pub fn new(keys: HmacKeys) -> Self {
+        HmacTokenStore { keys }
+    }
+}
+
+#[async_trait]
+impl TokenStore for HmacTokenStore {
+    async fn create(&self, token: &Token) -> Result<String, ApiError> {
+        let payload =
+            serde_json::to_vec(&Claims::from(token)).context("failed to serialize token claims")?;
+        self.keys
+            .sign(&base64::encode_config(payload, base64::URL_SAFE_NO_PAD))
+    }
+
+    async fn read(&self, token_id: &str) -> Result<Option<Token>, ApiError> {
+        let claims = self
+            .keys
+            .verify(token_id)
+            .and_then(|payload| base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok())
+            .and_then(|payload| serde_json::from_slice::<Claims>(&payload).ok());
+        Ok(claims.and_then(Claims::into_token))
+    }
+
+    fn is_self_contained(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokens::{
+        tests::{tamper, test_token},
+        SCOPE_ATTRIBUTE,
+    };
+    use chrono::{Duration, Utc};
+
+    fn store(keys: &[(&str, &[u8])]) -> HmacTokenStore {
+        let keys = keys
+            .iter()
+            .map(|(key_id, key)| (key_id.to_string(), key.to_vec()))
+            .collect();
+        HmacTokenStore::new(HmacKeys::new(keys).unwrap())
+    }
+
+    #[tokio::test]
+    async fn token_round_trips() {
+        let store = store(&[("k1", b"first secret key")]);
+        let token_id = store
+            .create(&test_token(Utc::now() + Duration::minutes(10)))
+            .await
+            .unwrap();
+        let token = store.read(&token_id).await.unwrap().unwrap();
+        assert_eq!(token.username, "alice");
+        assert_eq!(
+            token.attributes.get(SCOPE_ATTRIBUTE).map(String::as_str),
+            Some("read_message")
+        );
+    }
+
+    #[tokio::test]
+    async fn tampered_token_is_rejected() {
+        let store = store(&[("k1", b"first secret key")]);
+        let token_id = store
+            .create(&test_token(Utc::now() + Duration::minutes(10)))
+            .await
+            .unwrap();
+        assert!(store.read(&tamper(&token_id)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn expired_token_is_rejected() {
+        let store = store(&[("k1", b"first secret key")]);
+        let token_id = store
+            .create(&test_token(Utc::now() - Duration::minutes(1)))
+            .await
+            .unwrap();
+        assert!(store.read(&token_id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn revoking_a_token_is_not_supported() {
+        let store = store(&[("k1", b"first secret key")]);
+        let token_id = store
+            .create(&test_token(Utc::now() + Duration::minutes(10)))
+            .await
+            .unwrap();
+        let result = store.revoke(&token_id).await;
+        assert!(matches!(result, Err(ApiError::NotSupported(_))));
+        assert!(store.read(&token_id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn token_signed_with_retired_key_remains_valid() {
+        let old_store = store(&[("k1", b"first secret key")]);
+        let token_id = old_store
+            .create(&test_token(Utc::now() + Duration::minutes(10)))
+            .await
+            .unwrap();
+        let rotated_store = store(&[("k2", b"second secret key"), ("k1", b"first secret key")]);
+        assert!(rotated_store.read(&token_id).await.unwrap().is_some());
+        let new_store = store(&[("k2", b"second secret key")]);
+        assert!(new_store.read(&token_id).await.unwrap().is_none());
+    }
+}
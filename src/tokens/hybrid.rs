@@ -0,0 +1,207 @@
+use super::{database::DatabaseTokenStore, hmac::HmacKeys, Session, Token, TokenCache, TokenStore};
+use crate::error::ApiError;
+use axum::async_trait;
+use chrono::Duration;
+use sqlx::{postgres::PgListener, query, PgPool};
+use std::sync::Arc;
+
+const REVOCATION_CHANNEL: &str = "revoked_tokens";
+const MAX_CACHED_TOKENS: usize = 10_000;
+// Cached tokens are read from the database again once they are this old, which
+// keeps the last_used time of busy sessions accurate to within this interval.
+const CACHED_TOKEN_MAX_AGE_SECONDS: i64 = 60;
+
+pub struct HybridTokenStore {
+    db: PgPool,
+    tokens: DatabaseTokenStore,
+    keys: HmacKeys,
+    cache: Arc<TokenCache>,
+}
+
+impl HybridTokenStore {
+    pub fn new(db: PgPool, tokens: DatabaseTokenStore, keys: HmacKeys) -> Self {
+        HybridTokenStore {
+            db,
+            tokens,
+            keys,
+            cache: Arc::new(TokenCache::with_max_age(
+                MAX_CACHED_TOKENS,
+                Duration::seconds(CACHED_TOKEN_MAX_AGE_SECONDS),
+            )),
+        }
+    }
+
+    pub async fn spawn_revocation_listener(&self) -> anyhow::Result<()> {
+        let mut listener = PgListener::connect_with(&self.db).await?;
+        listener.listen(REVOCATION_CHANNEL).await?;
+        let cache = self.cache.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.recv().await {
+                    Ok(notification) => {
+                        cache.remove(notification.payload());
+                    }
+                    Err(e) => {
+                        tracing::warn!("lost token revocation notifications: {}", e);
+                        cache.clear();
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
+    async fn notify_revoked(&self, session_id: &str) -> Result<(), ApiError> {
+        self.cache.remove(session_id);
+        query!("SELECT pg_notify($1, $2)", REVOCATION_CHANNEL, session_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TokenStore for HybridTokenStore {
+    async fn create(&self, token: &Token) -> Result<String, ApiError> {
+        let token_id = self.tokens.create(token).await?;
+        self.keys.sign(&token_id)
+    }
+
+    async fn read(&self, token_id: &str) -> Result<Option<Token>, ApiError> {
+        let token_id = match self.keys.verify(token_id) {
+            Some(token_id) => token_id,
+            None => return Ok(None),
+        };
+        let session_id = self.tokens.session_id(token_id);
+        if let Some(token) = session_id.as_deref().and_then(|id| self.cache.get(id)) {
+            return Ok(Some(token));
+        }
+        let token = self.tokens.read(token_id).await?;
+        if let (Some(session_id), Some(token)) = (session_id, &token) {
+            self.cache.insert(session_id, token.clone());
+        }
+        Ok(token)
+    }
+
+    async fn revoke(&self, token_id: &str) -> Result<(), ApiError> {
+        let token_id = match self.keys.verify(token_id) {
+            Some(token_id) => token_id,
+            None => return Ok(()),
+        };
+        self.tokens.revoke(token_id).await?;
+        if let Some(session_id) = self.tokens.session_id(token_id) {
+            self.notify_revoked(&session_id).await?;
+        }
+        Ok(())
+    }
+
+    async fn list_sessions(&self, username: &str) -> Result<Vec<Session>, ApiError> {
+        self.tokens.list_sessions(username).await
+    }
+
+    fn session_id(&self, token_id: &str) -> Option<String> {
+        self.keys
+            .verify(token_id)
+            .and_then(|token_id| self.tokens.session_id(token_id))
+    }
+
+    async fn revoke_session(&self, username: &str, session_id: &str) -> Result<bool, ApiError> {
+        let is_revoked = self.tokens.revoke_session(username, session_id).await?;
+        if is_revoked {
+            self.notify_revoked(session_id).await?;
+        }
+        Ok(is_revoked)
+    }
+
+    async fn forget_sessions(&self, session_ids: &[String]) -> Result<(), ApiError> {
+        for session_id in session_ids {
+            self.notify_revoked(session_id).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middlewares::tests::{context, create_user, delete_user};
+    use crate::tokens::tests::tamper;
+    use chrono::Utc;
+    use std::time::Duration as StdDuration;
+
+    fn store(db: &PgPool) -> HybridTokenStore {
+        let keys = HmacKeys::new(vec![("k1".to_string(), vec![7u8; 32])]).unwrap();
+        HybridTokenStore::new(db.clone(), DatabaseTokenStore::new(db.clone()), keys)
+    }
+
+    async fn last_used(db: &PgPool, session_id: &str) -> Option<chrono::DateTime<Utc>> {
+        query!(
+            "SELECT last_used FROM tokens WHERE token_id = $1",
+            session_id
+        )
+        .fetch_one(db)
+        .await
+        .unwrap()
+        .last_used
+    }
+
+    #[tokio::test]
+    async fn only_signed_tokens_are_accepted() {
+        let ctx = context().await;
+        let username = create_user(&ctx).await;
+        let store = store(&ctx.db);
+        let token = Token::new(Utc::now() + Duration::minutes(10), username.clone());
+        let token_id = store.create(&token).await.unwrap();
+        assert!(store.read(&token_id).await.unwrap().is_some());
+        let database_token_id = store.keys.verify(&token_id).unwrap();
+        assert!(store.read(database_token_id).await.unwrap().is_none());
+        assert!(store.read(&tamper(&token_id)).await.unwrap().is_none());
+        delete_user(&ctx, &username).await;
+    }
+
+    #[tokio::test]
+    async fn stale_cache_entries_update_last_used() {
+        let ctx = context().await;
+        let username = create_user(&ctx).await;
+        let store = HybridTokenStore {
+            cache: Arc::new(TokenCache::with_max_age(10, Duration::milliseconds(50))),
+            ..store(&ctx.db)
+        };
+        let token = Token::new(Utc::now() + Duration::minutes(10), username.clone());
+        let token_id = store.create(&token).await.unwrap();
+        let session_id = store.session_id(&token_id).unwrap();
+        store.read(&token_id).await.unwrap().unwrap();
+        let first_use = last_used(&ctx.db, &session_id).await.unwrap();
+        // A cache hit does not touch the database.
+        store.read(&token_id).await.unwrap().unwrap();
+        assert_eq!(last_used(&ctx.db, &session_id).await, Some(first_use));
+        tokio::time::sleep(StdDuration::from_millis(100)).await;
+        store.read(&token_id).await.unwrap().unwrap();
+        assert!(last_used(&ctx.db, &session_id).await.unwrap() > first_use);
+        delete_user(&ctx, &username).await;
+    }
+
+    #[tokio::test]
+    async fn revocations_are_broadcast_to_other_instances() {
+        let ctx = context().await;
+        let username = create_user(&ctx).await;
+        let local = store(&ctx.db);
+        let remote = store(&ctx.db);
+        remote.spawn_revocation_listener().await.unwrap();
+        let token = Token::new(Utc::now() + Duration::minutes(10), username.clone());
+        let token_id = local.create(&token).await.unwrap();
+        assert!(remote.read(&token_id).await.unwrap().is_some());
+        let session_id = remote.session_id(&token_id).unwrap();
+        assert!(remote.cache.get(&session_id).is_some());
+        local.revoke(&token_id).await.unwrap();
+        for _ in 0..50 {
+            if remote.cache.get(&session_id).is_none() {
+                break;
+            }
+            tokio::time::sleep(StdDuration::from_millis(20)).await;
+        }
+        assert!(remote.cache.get(&session_id).is_none());
+        assert!(remote.read(&token_id).await.unwrap().is_none());
+        delete_user(&ctx, &username).await;
+    }
+}
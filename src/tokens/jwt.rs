@@ -0,0 +1,397 @@
+use super::{issued_at, Token, TokenStore, ISSUED_AT_ATTRIBUTE, SCOPE_ATTRIBUTE};
+use crate::error::ApiError;
+use anyhow::{anyhow, Context};
+use axum::async_trait;
+use chrono::{TimeZone, Utc};
+use clap::ValueEnum;
+use jsonwebtoken::{
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use ring::signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum JwtAlgorithm {
+    Hs256,
+    Es256,
+    Eddsa,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Jwk {
+    kty: &'static str,
+    crv: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    x: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    y: Option<String>,
+    kid: String,
+    alg: &'static str,
+    #[serde(rename = "use")]
+    key_use: &'static str,
+}
+
+struct JwtKey {
+    key_id: String,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    jwk: Option<Jwk>,
+}
+
+impl JwtKey {
+    fn new(algorithm: JwtAlgorithm, key_id: String, key: &[u8]) -> anyhow::Result<Self> {
+        match algorithm {
+            JwtAlgorithm::Hs256 => Ok(JwtKey {
+                key_id,
+                encoding_key: EncodingKey::from_secret(key),
+                decoding_key: DecodingKey::from_secret(key),
+                jwk: None,
+            }),
+            JwtAlgorithm::Es256 => {
+                let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, key)
+                    .map_err(|e| anyhow!("key {} is not a P-256 PKCS#8 key: {}", key_id, e))?;
+                let public_key = key_pair.public_key().as_ref();
+                let (x, y) = public_key[1..].split_at(32);
+                Ok(JwtKey {
+                    encoding_key: EncodingKey::from_ec_der(key),
+                    decoding_key: DecodingKey::from_ec_der(public_key),
+                    jwk: Some(Jwk {
+                        kty: "EC",
+                        crv: "P-256",
+                        x: Some(base64::encode_config(x, base64::URL_SAFE_NO_PAD)),
+                        y: Some(base64::encode_config(y, base64::URL_SAFE_NO_PAD)),
+                        kid: key_id.clone(),
+                        alg: "ES256",
+                        key_use: "sig",
+                    }),
+                    key_id,
+                })
+            }
+            JwtAlgorithm::Eddsa => {
+                let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(key)
+                    .map_err(|e| anyhow!("key {} is not an Ed25519 PKCS#8 key: {}", key_id, e))?;
+                let public_key = key_pair.public_key().as_ref();
+                Ok(JwtKey {
+                    encoding_key: EncodingKey::from_ed_der(key),
+                    decoding_key: DecodingKey::from_ed_der(public_key),
+                    jwk: Some(Jwk {
+                        kty: "OKP",
+                        crv: "Ed25519",
+                        x: Some(base64::encode_config(public_key, base64::URL_SAFE_NO_PAD)),
+                        y: None,
+                        kid: key_id.clone(),
+                        alg: "EdDSA",
+                        key_use: "sig",
+                    }),
+                    key_id,
+                })
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    exp: i64,
+    nbf: i64,
+    iat: i64,
+    iss: String,
+    aud: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
+    #[serde(flatten)]
+    attrs: HashMap<String, Value>,
+}
+
+pub struct JwtTokenStore {
+    algorithm: Algorithm,
+    keys: Vec<JwtKey>,
+    issuer: String,
+    audience: String,
+}
+
+impl JwtTokenStore {
+    pub fn new(
+        algorithm: JwtAlgorithm,
+        keys: Vec<(String, Vec<u8>)>,
+        issuer: String,
+        audience: String,
+    ) -> anyhow::Result<Self> {
+        if keys.is_empty() {
+            return Err(anyhow!("at least one JWT key must be configured"));
+        }
+        let keys = keys
+            .into_iter()
+            .map(|(key_id, key)| JwtKey::new(algorithm, key_id, &key))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let algorithm = match algorithm {
+            JwtAlgorithm::Hs256 => Algorithm::HS256,
+            JwtAlgorithm::Es256 => Algorithm::ES256,
+            JwtAlgorithm::Eddsa => Algorithm::EdDSA,
+        };
+        Ok(JwtTokenStore {
+            algorithm,
+            keys,
+            issuer,
+            audience,
+        })
+    }
+}
+
+#[async_trait]
+impl TokenStore for JwtTokenStore {
+    async fn create(&self, token: &Token) -> Result<String, ApiError> {
+        let key = &self.keys[0];
+        let mut header = Header::new(self.algorithm);
+        header.kid = Some(key.key_id.clone());
+        let mut attrs = token.attributes.clone();
+        attrs.insert(ISSUED_AT_ATTRIBUTE.to_string(), issued_at());
+        let now = Utc::now().timestamp();
+        let claims = Claims {
+            sub: token.username.clone(),
+            exp: token.expiry.timestamp(),
+            nbf: now,
+            iat: now,
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
+            scope: attrs.remove(SCOPE_ATTRIBUTE),
+            attrs: attrs
+                .into_iter()
+                .map(|(key, value)| (key, Value::String(value)))
+                .collect(),
+        };
+        let jwt = encode(&header, &claims, &key.encoding_key).context("failed to sign JWT")?;
+        Ok(jwt)
+    }
+
+    async fn read(&self, token_id: &str) -> Result<Option<Token>, ApiError> {
+        let key = decode_header(token_id).ok().and_then(|header| {
+            let key_id = header.kid?;
+            self.keys.iter().find(|key| key.key_id == key_id)
+        });
+        let key = match key {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        let mut validation = Validation::new(self.algorithm);
+        validation.set_audience(&[&self.audience]);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_required_spec_claims(&["exp", "nbf", "aud", "iss", "sub"]);
+        validation.validate_nbf = true;
+        let claims = match decode::<Claims>(token_id, &key.decoding_key, &validation) {
+            Ok(data) => data.claims,
+            Err(_) => return Ok(None),
+        };
+        let expiry = match Utc.timestamp_opt(claims.exp, 0).single() {
+            Some(expiry) => expiry,
+            None => return Ok(None),
+        };
+        let mut token = Token::new(expiry, claims.sub);
+        for (key, value) in claims.attrs {
+            if let Value::String(value) = value {
+                token.attributes.insert(key, value);
+            }
+        }
+        if let Some(scope) = claims.scope {
+            token.attributes.insert(SCOPE_ATTRIBUTE.to_string(), scope);
+        }
+        Ok(Some(token))
+    }
+
+    fn public_keys(&self) -> Vec<Jwk> {
+        self.keys.iter().filter_map(|key| key.jwk.clone()).collect()
+    }
+
+    fn is_self_contained(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokens::{
+        tests::{tamper, test_token},
+        MFA_ATTRIBUTE,
+    };
+    use chrono::Duration;
+    use ring::rand::SystemRandom;
+
+    const ISSUER: &str = "https://localhost:8000";
+
+    fn store(algorithm: JwtAlgorithm, key: Vec<u8>) -> JwtTokenStore {
+        JwtTokenStore::new(
+            algorithm,
+            vec![("k1".to_string(), key)],
+            ISSUER.to_string(),
+            ISSUER.to_string(),
+        )
+        .unwrap()
+    }
+
+    fn stores() -> Vec<JwtTokenStore> {
+        let rng = SystemRandom::new();
+        let es256_key =
+            EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let eddsa_key = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        vec![
+            store(JwtAlgorithm::Hs256, b"an HS256 secret key".to_vec()),
+            store(JwtAlgorithm::Es256, es256_key.as_ref().to_vec()),
+            store(JwtAlgorithm::Eddsa, eddsa_key.as_ref().to_vec()),
+        ]
+    }
+
+    #[tokio::test]
+    async fn token_round_trips() {
+        for store in stores() {
+            let token_id = store
+                .create(&test_token(Utc::now() + Duration::minutes(10)))
+                .await
+                .unwrap();
+            let token = store.read(&token_id).await.unwrap().unwrap();
+            assert_eq!(token.username, "alice");
+            assert_eq!(
+                token.attributes.get(SCOPE_ATTRIBUTE).map(String::as_str),
+                Some("read_message")
+            );
+            assert_eq!(
+                token.attributes.get(MFA_ATTRIBUTE).map(String::as_str),
+                Some("true")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn tampered_token_is_rejected() {
+        for store in stores() {
+            let token_id = store
+                .create(&test_token(Utc::now() + Duration::minutes(10)))
+                .await
+                .unwrap();
+            assert!(store.read(&tamper(&token_id)).await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_token_is_rejected() {
+        // Expiry is checked with a minute of leeway for clock skew.
+        for store in stores() {
+            let token_id = store
+                .create(&test_token(Utc::now() - Duration::minutes(5)))
+                .await
+                .unwrap();
+            assert!(store.read(&token_id).await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn token_for_another_audience_is_rejected() {
+        let key = b"an HS256 secret key".to_vec();
+        let other = JwtTokenStore::new(
+            JwtAlgorithm::Hs256,
+            vec![("k1".to_string(), key.clone())],
+            ISSUER.to_string(),
+            "https://other.example".to_string(),
+        )
+        .unwrap();
+        let token_id = other
+            .create(&test_token(Utc::now() + Duration::minutes(10)))
+            .await
+            .unwrap();
+        let store = store(JwtAlgorithm::Hs256, key);
+        assert!(store.read(&token_id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn token_from_another_issuer_is_rejected() {
+        let key = b"an HS256 secret key".to_vec();
+        let other = JwtTokenStore::new(
+            JwtAlgorithm::Hs256,
+            vec![("k1".to_string(), key.clone())],
+            "https://other.example".to_string(),
+            ISSUER.to_string(),
+        )
+        .unwrap();
+        let token_id = other
+            .create(&test_token(Utc::now() + Duration::minutes(10)))
+            .await
+            .unwrap();
+        let store = store(JwtAlgorithm::Hs256, key);
+        assert!(store.read(&token_id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn token_used_before_not_before_is_rejected() {
+        let store = store(JwtAlgorithm::Hs256, b"an HS256 secret key".to_vec());
+        let key = &store.keys[0];
+        let mut header = Header::new(store.algorithm);
+        header.kid = Some(key.key_id.clone());
+        let now = Utc::now();
+        let claims = Claims {
+            sub: "alice".to_string(),
+            exp: (now + Duration::minutes(20)).timestamp(),
+            nbf: (now + Duration::minutes(10)).timestamp(),
+            iat: now.timestamp(),
+            iss: ISSUER.to_string(),
+            aud: ISSUER.to_string(),
+            scope: None,
+            attrs: HashMap::new(),
+        };
+        let token_id = encode(&header, &claims, &key.encoding_key).unwrap();
+        assert!(store.read(&token_id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn tokens_signed_with_a_retired_key_stay_valid() {
+        let old_key = b"the old HS256 secret key".to_vec();
+        let new_key = b"the new HS256 secret key".to_vec();
+        let old = store(JwtAlgorithm::Hs256, old_key.clone());
+        let token_id = old
+            .create(&test_token(Utc::now() + Duration::minutes(10)))
+            .await
+            .unwrap();
+        let rotated = JwtTokenStore::new(
+            JwtAlgorithm::Hs256,
+            vec![
+                ("k2".to_string(), new_key.clone()),
+                ("k1".to_string(), old_key),
+            ],
+            ISSUER.to_string(),
+            ISSUER.to_string(),
+        )
+        .unwrap();
+        assert!(rotated.read(&token_id).await.unwrap().is_some());
+        let new_token_id = rotated
+            .create(&test_token(Utc::now() + Duration::minutes(10)))
+            .await
+            .unwrap();
+        let header = decode_header(&new_token_id).unwrap();
+        assert_eq!(header.kid.as_deref(), Some("k2"));
+        let retired = JwtTokenStore::new(
+            JwtAlgorithm::Hs256,
+            vec![("k2".to_string(), new_key)],
+            ISSUER.to_string(),
+            ISSUER.to_string(),
+        )
+        .unwrap();
+        assert!(retired.read(&token_id).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn only_asymmetric_keys_are_published() {
+        let stores = stores();
+        assert!(stores[0].public_keys().is_empty());
+        let es256 = serde_json::to_value(stores[1].public_keys()).unwrap();
+        assert_eq!(es256[0]["kty"], "EC");
+        assert_eq!(es256[0]["alg"], "ES256");
+        assert_eq!(es256[0]["kid"], "k1");
+        assert!(es256[0]["y"].is_string());
+        let eddsa = serde_json::to_value(stores[2].public_keys()).unwrap();
+        assert_eq!(eddsa[0]["kty"], "OKP");
+        assert_eq!(eddsa[0]["alg"], "EdDSA");
+        assert!(eddsa[0].get("y").is_none());
+    }
+}
@@ -0,0 +1,391 @@
+pub mod database;
+pub mod encrypted;
+pub mod hmac;
+pub mod hybrid;
+pub mod jwt;
+pub mod oauth2;
+pub mod refresh;
+
+use crate::error::ApiError;
+use anyhow::{anyhow, Context};
+use axum::async_trait;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use jwt::Jwk;
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{query, query_scalar, PgPool, Postgres, Transaction};
+use std::{collections::HashMap, sync::Mutex};
+use subtle::ConstantTimeEq;
+
+pub const USER_AGENT_ATTRIBUTE: &str = "user_agent";
+pub const SCOPE_ATTRIBUTE: &str = "scope";
+pub const MFA_ATTRIBUTE: &str = "mfa";
+pub const AUTH_TIME_ATTRIBUTE: &str = "auth_time";
+pub const CLIENT_ID_ATTRIBUTE: &str = "client_id";
+pub const ISSUED_AT_ATTRIBUTE: &str = "issued_at";
+pub const SESSION_COOKIE: &str = "__Host-token";
+pub const CSRF_TOKEN_HEADER: &str = "x-csrf-token";
+
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub expiry: DateTime<Utc>,
+    pub username: String,
+    pub attributes: HashMap<String, String>,
+}
+
+impl Token {
+    pub fn new(expiry: DateTime<Utc>, username: String) -> Self {
+        Token {
+            expiry,
+            username,
+            attributes: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    exp: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    attrs: HashMap<String, String>,
+}
+
+impl From<&Token> for Claims {
+    fn from(token: &Token) -> Self {
+        let mut attrs = token.attributes.clone();
+        attrs.insert(ISSUED_AT_ATTRIBUTE.to_string(), issued_at());
+        Claims {
+            sub: token.username.clone(),
+            exp: token.expiry.timestamp(),
+            scope: attrs.remove(SCOPE_ATTRIBUTE),
+            attrs,
+        }
+    }
+}
+
+impl Claims {
+    fn into_token(self) -> Option<Token> {
+        let expiry = Utc
+            .timestamp_opt(self.exp, 0)
+            .single()
+            .filter(|expiry| *expiry > Utc::now())?;
+        let mut token = Token::new(expiry, self.sub);
+        token.attributes = self.attrs;
+        if let Some(scope) = self.scope {
+            token.attributes.insert(SCOPE_ATTRIBUTE.to_string(), scope);
+        }
+        Some(token)
+    }
+}
+
+#[async_trait]
+pub trait TokenStore: Send + Sync {
+    async fn create(&self, token: &Token) -> Result<String, ApiError>;
+
+    async fn read(&self, token_id: &str) -> Result<Option<Token>, ApiError>;
+
+    // Self-contained tokens are not tracked anywhere and stay valid until they
+    // expire, so stores that issue them cannot revoke a single token.
+    async fn revoke(&self, _token_id: &str) -> Result<(), ApiError> {
+        Err(ApiError::NotSupported("revoking tokens"))
+    }
+
+    async fn list_sessions(&self, _username: &str) -> Result<Vec<Session>, ApiError> {
+        Err(ApiError::NotSupported("listing sessions"))
+    }
+
+    fn session_id(&self, _token_id: &str) -> Option<String> {
+        None
+    }
+
+    async fn revoke_session(&self, _username: &str, _session_id: &str) -> Result<bool, ApiError> {
+        Err(ApiError::NotSupported("revoking sessions"))
+    }
+
+    fn public_keys(&self) -> Vec<Jwk> {
+        Vec::new()
+    }
+
+    // Self-contained tokens cannot be deleted when their user's credentials
+    // change, so `authenticate` checks them with `is_current` instead.
+    fn is_self_contained(&self) -> bool {
+        false
+    }
+
+    // Drops sessions that `invalidate_user_tokens` deleted from the database
+    // from any cache the store keeps.
+    async fn forget_sessions(&self, _session_ids: &[String]) -> Result<(), ApiError> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub id: String,
+    pub created: DateTime<Utc>,
+    pub last_used: Option<DateTime<Utc>>,
+    pub expiry: DateTime<Utc>,
+    pub user_agent: Option<String>,
+}
+
+// Session ids are plain hashes of the token and are listed by GET /sessions,
+// so the CSRF token is hashed under its own prefix to keep the two unrelated.
+const CSRF_TOKEN_PREFIX: &str = "natter-csrf:";
+
+pub fn csrf_token(token_id: &str) -> String {
+    hash(&format!("{}{}", CSRF_TOKEN_PREFIX, token_id))
+}
+
+pub fn verify_csrf_token(token_id: &str, csrf_token: &str) -> bool {
+    self::csrf_token(token_id)
+        .as_bytes()
+        .ct_eq(csrf_token.as_bytes())
+        .into()
+}
+
+/// Invalidates every token and refresh token of a user as part of
+/// `transaction`, so that the revocation commits together with the credential
+/// change that caused it. Returns the ids of the deleted sessions, which must
+/// be passed to [`TokenStore::forget_sessions`] once committed.
+pub async fn invalidate_user_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    username: &str,
+) -> Result<Vec<String>, ApiError> {
+    query!(
+        "UPDATE users SET tokens_valid_after = $2 WHERE user_id = $1",
+        username,
+        Utc::now()
+    )
+    .execute(&mut *transaction)
+    .await?;
+    query!("DELETE FROM refresh_tokens WHERE user_id = $1", username)
+        .execute(&mut *transaction)
+        .await?;
+    let session_ids = query_scalar!(
+        "DELETE FROM tokens WHERE user_id = $1 RETURNING token_id",
+        username
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    Ok(session_ids)
+}
+
+/// Whether a self-contained token was issued after its user's tokens were last
+/// invalidated. Tokens of deleted users are never current.
+pub async fn is_current(db: &PgPool, token: &Token) -> Result<bool, ApiError> {
+    let valid_after = query_scalar!(
+        "SELECT tokens_valid_after FROM users WHERE user_id = $1",
+        token.username
+    )
+    .fetch_optional(db)
+    .await?;
+    let issued_at = token
+        .attributes
+        .get(ISSUED_AT_ATTRIBUTE)
+        .and_then(|issued_at| issued_at.parse::<i64>().ok());
+    Ok(match valid_after {
+        None => false,
+        Some(None) => true,
+        Some(Some(valid_after)) => {
+            issued_at.is_some_and(|issued_at| issued_at >= valid_after.timestamp_millis())
+        }
+    })
+}
+
+fn issued_at() -> String {
+    Utc::now().timestamp_millis().to_string()
+}
+
+pub fn random_id() -> String {
+    let mut bytes = [0u8; 20];
+    OsRng.fill_bytes(&mut bytes);
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+pub fn hash(value: &str) -> String {
+    let digest = Sha256::digest(value.as_bytes());
+    base64::encode_config(digest, base64::URL_SAFE_NO_PAD)
+}
+
+struct CachedToken {
+    token: Token,
+    cached_at: DateTime<Utc>,
+}
+
+pub struct TokenCache {
+    capacity: usize,
+    max_age: Option<Duration>,
+    entries: Mutex<HashMap<String, CachedToken>>,
+}
+
+impl TokenCache {
+    pub fn new(capacity: usize) -> Self {
+        TokenCache {
+            capacity,
+            max_age: None,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// A cache whose entries are dropped once they are `max_age` old, even if
+    /// the token itself is still valid.
+    pub fn with_max_age(capacity: usize, max_age: Duration) -> Self {
+        TokenCache {
+            max_age: Some(max_age),
+            ..TokenCache::new(capacity)
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<Token> {
+        let now = Utc::now();
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(entry) if entry.token.expiry > now && !self.is_stale(entry, now) => {
+                Some(entry.token.clone())
+            }
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn is_stale(&self, entry: &CachedToken, now: DateTime<Utc>) -> bool {
+        self.max_age
+            .is_some_and(|max_age| now - entry.cached_at > max_age)
+    }
+
+    pub fn insert(&self, key: String, token: Token) {
+        let now = Utc::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            entries.retain(|_, entry| entry.token.expiry > now && !self.is_stale(entry, now));
+            if entries.len() >= self.capacity {
+                // Evicts the token that would have expired first.
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.token.expiry)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+        entries.insert(
+            key,
+            CachedToken {
+                token,
+                cached_at: now,
+            },
+        );
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+pub fn parse_keys(keys: &[String]) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    keys.iter()
+        .map(|key| {
+            let (key_id, secret) = key
+                .split_once(':')
+                .ok_or_else(|| anyhow!("key must be in the form <key id>:<base64 secret>"))?;
+            let secret = base64::decode(secret)
+                .with_context(|| format!("key {} is not valid base64", key_id))?;
+            Ok((key_id.to_string(), secret))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database::DatabaseTokenStore;
+    use sqlx::PgPool;
+
+    pub(super) fn test_token(expiry: DateTime<Utc>) -> Token {
+        let mut token = Token::new(expiry, "alice".to_string());
+        token
+            .attributes
+            .insert(SCOPE_ATTRIBUTE.to_string(), "read_message".to_string());
+        token
+            .attributes
+            .insert(MFA_ATTRIBUTE.to_string(), true.to_string());
+        token
+    }
+
+    /// Changes one character of the part of `token` after its first `.`.
+    pub(super) fn tamper(token: &str) -> String {
+        let index = token.find('.').unwrap() + 8;
+        let replacement = if &token[index..index + 1] == "A" {
+            "B"
+        } else {
+            "A"
+        };
+        format!("{}{}{}", &token[..index], replacement, &token[index + 1..])
+    }
+
+    #[tokio::test]
+    async fn csrf_token_differs_from_session_id() {
+        let store = DatabaseTokenStore::new(PgPool::connect_lazy("postgres://localhost").unwrap());
+        let token_id = random_id();
+        let csrf_token = csrf_token(&token_id);
+        assert_ne!(Some(&csrf_token), store.session_id(&token_id).as_ref());
+        assert!(verify_csrf_token(&token_id, &csrf_token));
+        assert!(!verify_csrf_token(&token_id, &hash(&token_id)));
+    }
+
+    #[test]
+    fn cache_returns_live_tokens() {
+        let cache = TokenCache::new(10);
+        cache.insert(
+            "a".to_string(),
+            test_token(Utc::now() + Duration::minutes(10)),
+        );
+        assert_eq!(cache.get("a").unwrap().username, "alice");
+        assert!(cache.get("b").is_none());
+        cache.remove("a");
+        assert!(cache.get("a").is_none());
+    }
+
+    #[test]
+    fn cache_drops_expired_and_stale_tokens() {
+        let cache = TokenCache::new(10);
+        cache.insert(
+            "a".to_string(),
+            test_token(Utc::now() - Duration::seconds(1)),
+        );
+        assert!(cache.get("a").is_none());
+        let cache = TokenCache::with_max_age(10, Duration::milliseconds(1));
+        cache.insert(
+            "a".to_string(),
+            test_token(Utc::now() + Duration::minutes(10)),
+        );
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert!(cache.get("a").is_none());
+    }
+
+    #[test]
+    fn full_cache_evicts_the_token_expiring_first() {
+        let cache = TokenCache::new(3);
+        let now = Utc::now();
+        cache.insert("b".to_string(), test_token(now + Duration::minutes(2)));
+        cache.insert("a".to_string(), test_token(now + Duration::minutes(1)));
+        cache.insert("c".to_string(), test_token(now + Duration::minutes(3)));
+        cache.insert("d".to_string(), test_token(now + Duration::minutes(4)));
+        assert!(cache.get("a").is_none());
+        for key in ["b", "c", "d"] {
+            assert!(cache.get(key).is_some());
+        }
+    }
+}
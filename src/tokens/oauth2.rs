@@ -0,0 +1,257 @@
+use super::{hash, Token, TokenCache, TokenStore, SCOPE_ATTRIBUTE};
+use crate::error::ApiError;
+use anyhow::{anyhow, Context};
+use axum::async_trait;
+use chrono::{TimeZone, Utc};
+use serde::Deserialize;
+use std::time::Duration;
+
+const MAX_CACHED_TOKENS: usize = 10_000;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, audience: &str) -> bool {
+        match self {
+            Audience::One(aud) => aud == audience,
+            Audience::Many(auds) => auds.iter().any(|aud| aud == audience),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct IntrospectionResponse {
+    active: bool,
+    sub: Option<String>,
+    scope: Option<String>,
+    exp: Option<i64>,
+    aud: Option<Audience>,
+}
+
+pub struct IntrospectionTokenStore {
+    client: reqwest::Client,
+    introspection_endpoint: String,
+    revocation_endpoint: Option<String>,
+    audience: Option<String>,
+    client_id: String,
+    client_secret: String,
+    cache: TokenCache,
+}
+
+impl IntrospectionTokenStore {
+    pub fn new(
+        introspection_endpoint: String,
+        revocation_endpoint: Option<String>,
+        audience: Option<String>,
+        client_id: String,
+        client_secret: String,
+    ) -> anyhow::Result<Self> {
+        // Every authenticated request waits on this client, so a stalled
+        // authorization server must not hang them indefinitely.
+        let client = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .context("failed to build token introspection client")?;
+        Ok(IntrospectionTokenStore {
+            client,
+            introspection_endpoint,
+            revocation_endpoint,
+            audience,
+            client_id,
+            client_secret,
+            cache: TokenCache::new(MAX_CACHED_TOKENS),
+        })
+    }
+}
+
+#[async_trait]
+impl TokenStore for IntrospectionTokenStore {
+    async fn create(&self, _token: &Token) -> Result<String, ApiError> {
+        Err(ApiError::BadRequest(
+            "tokens must be obtained from the authorization server".to_string(),
+        ))
+    }
+
+    async fn read(&self, token_id: &str) -> Result<Option<Token>, ApiError> {
+        let cache_key = hash(token_id);
+        if let Some(token) = self.cache.get(&cache_key) {
+            return Ok(Some(token));
+        }
+        let response = self
+            .client
+            .post(&self.introspection_endpoint)
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(&[("token", token_id), ("token_type_hint", "access_token")])
+            .send()
+            .await
+            .context("failed to call token introspection endpoint")?;
+        if !response.status().is_success() {
+            return Err(ApiError::ServerError(anyhow!(
+                "token introspection endpoint returned {}",
+                response.status()
+            )));
+        }
+        let response = response
+            .json::<IntrospectionResponse>()
+            .await
+            .context("invalid token introspection response")?;
+        if !response.active {
+            return Ok(None);
+        }
+        if let Some(audience) = &self.audience {
+            if !response.aud.is_some_and(|aud| aud.contains(audience)) {
+                return Ok(None);
+            }
+        }
+        let expiry = response
+            .exp
+            .and_then(|exp| Utc.timestamp_opt(exp, 0).single())
+            .filter(|expiry| *expiry > Utc::now());
+        let (username, expiry) = match (response.sub, expiry) {
+            (Some(username), Some(expiry)) => (username, expiry),
+            _ => return Ok(None),
+        };
+        let mut token = Token::new(expiry, username);
+        if let Some(scope) = response.scope {
+            token.attributes.insert(SCOPE_ATTRIBUTE.to_string(), scope);
+        }
+        self.cache.insert(cache_key, token.clone());
+        Ok(Some(token))
+    }
+
+    async fn revoke(&self, token_id: &str) -> Result<(), ApiError> {
+        self.cache.remove(&hash(token_id));
+        if let Some(revocation_endpoint) = &self.revocation_endpoint {
+            let response = self
+                .client
+                .post(revocation_endpoint)
+                .basic_auth(&self.client_id, Some(&self.client_secret))
+                .form(&[("token", token_id), ("token_type_hint", "access_token")])
+                .send()
+                .await
+                .context("failed to call token revocation endpoint")?;
+            if !response.status().is_success() {
+                return Err(ApiError::ServerError(anyhow!(
+                    "token revocation endpoint returned {}",
+                    response.status()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        extract::{Form, TypedHeader},
+        headers::{authorization::Basic, Authorization},
+        http::StatusCode,
+        response::{IntoResponse, Response},
+        routing::post,
+        Json, Router, Server,
+    };
+    use serde_json::json;
+    use std::{collections::HashMap, net::TcpListener};
+
+    const CLIENT_ID: &str = "natter";
+    const CLIENT_SECRET: &str = "secret";
+    const AUDIENCE: &str = "https://natter.example";
+
+    async fn introspect(
+        TypedHeader(credentials): TypedHeader<Authorization<Basic>>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> Response {
+        if credentials.username() != CLIENT_ID || credentials.password() != CLIENT_SECRET {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+        let exp = Utc::now().timestamp() + 600;
+        let body = match form.get("token").map(String::as_str) {
+            Some("active") => json!({
+                "active": true,
+                "sub": "alice",
+                "scope": "read_message list_messages",
+                "exp": exp,
+                "aud": [AUDIENCE, "https://other.example"],
+            }),
+            Some("expired") => json!({
+                "active": true,
+                "sub": "alice",
+                "exp": Utc::now().timestamp() - 60,
+                "aud": AUDIENCE,
+            }),
+            Some("wrong-audience") => json!({
+                "active": true,
+                "sub": "alice",
+                "exp": exp,
+                "aud": "https://other.example",
+            }),
+            Some("error") => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            _ => json!({ "active": false }),
+        };
+        Json(body).into_response()
+    }
+
+    fn mock_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/introspect", post(introspect));
+        let server = Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+        format!("http://{}/introspect", addr)
+    }
+
+    fn store() -> IntrospectionTokenStore {
+        IntrospectionTokenStore::new(
+            mock_server(),
+            None,
+            Some(AUDIENCE.to_string()),
+            CLIENT_ID.to_string(),
+            CLIENT_SECRET.to_string(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn active_token_maps_subject_and_scope() {
+        let token = store().read("active").await.unwrap().unwrap();
+        assert_eq!(token.username, "alice");
+        assert_eq!(
+            token.attributes.get(SCOPE_ATTRIBUTE).map(String::as_str),
+            Some("read_message list_messages")
+        );
+        assert!(token.expiry > Utc::now());
+    }
+
+    #[tokio::test]
+    async fn inactive_token_is_rejected() {
+        assert!(store().read("inactive").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn expired_token_is_rejected() {
+        assert!(store().read("expired").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn token_for_another_audience_is_rejected() {
+        assert!(store().read("wrong-audience").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn server_error_is_not_treated_as_inactive() {
+        let result = store().read("error").await;
+        assert!(matches!(result, Err(ApiError::ServerError(_))));
+    }
+}
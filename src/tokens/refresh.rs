@@ -0,0 +1,244 @@
+use super::{hash, random_id, TokenStore};
+use crate::error::ApiError;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{query, PgPool};
+use std::{collections::HashMap, sync::Arc};
+
+#[derive(Clone, Debug)]
+pub struct RefreshToken {
+    pub expiry: DateTime<Utc>,
+    pub username: String,
+    pub client_id: Option<String>,
+    pub scope: Option<String>,
+    pub family_id: String,
+    pub session_id: Option<String>,
+    pub attributes: HashMap<String, String>,
+}
+
+#[derive(Clone)]
+pub struct RefreshTokenStore {
+    db: PgPool,
+    tokens: Arc<dyn TokenStore>,
+}
+
+impl RefreshTokenStore {
+    pub fn new(db: PgPool, tokens: Arc<dyn TokenStore>) -> Self {
+        RefreshTokenStore { db, tokens }
+    }
+
+    pub fn new_family_id() -> String {
+        random_id()
+    }
+
+    pub async fn create(&self, token: &RefreshToken) -> Result<String, ApiError> {
+        let token_id = random_id();
+        let attributes = serde_json::to_string(&token.attributes)
+            .context("failed to serialize refresh token attributes")?;
+        query!(
+            "INSERT INTO refresh_tokens (token_hash, user_id, client_id, scope, expiry, family_id, session_id, attributes) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            hash(&token_id),
+            token.username,
+            token.client_id,
+            token.scope,
+            token.expiry,
+            token.family_id,
+            token.session_id,
+            attributes
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(token_id)
+    }
+
+    /// Consumes a refresh token issued to `client_id`, or directly to the user
+    /// when `None`. A token presented by anyone else is left untouched, so it
+    /// neither burns the token nor counts as a replay of it.
+    pub async fn redeem(
+        &self,
+        token_id: &str,
+        client_id: Option<&str>,
+    ) -> Result<Option<RefreshToken>, ApiError> {
+        let mut transaction = self.db.begin().await?;
+        let record = query!(
+            "SELECT user_id, client_id, scope, expiry, family_id, session_id, attributes, used FROM refresh_tokens WHERE token_hash = $1 FOR UPDATE",
+            hash(token_id)
+        )
+        .fetch_optional(&mut transaction)
+        .await?;
+        let record = match record {
+            Some(record) if record.client_id.as_deref() == client_id => record,
+            _ => {
+                transaction.rollback().await?;
+                return Ok(None);
+            }
+        };
+        if record.used {
+            transaction.rollback().await?;
+            tracing::warn!(
+                "refresh token reused, revoking token family of {}",
+                record.user_id
+            );
+            self.revoke_family(&record.family_id).await?;
+            return Ok(None);
+        }
+        query!(
+            "UPDATE refresh_tokens SET used = true WHERE token_hash = $1",
+            hash(token_id)
+        )
+        .execute(&mut transaction)
+        .await?;
+        transaction.commit().await?;
+        if record.expiry <= Utc::now() {
+            return Ok(None);
+        }
+        let attributes = serde_json::from_str(&record.attributes)
+            .context("failed to deserialize refresh token attributes")?;
+        Ok(Some(RefreshToken {
+            expiry: record.expiry,
+            username: record.user_id,
+            client_id: record.client_id,
+            scope: record.scope,
+            family_id: record.family_id,
+            session_id: record.session_id,
+            attributes,
+        }))
+    }
+
+    pub async fn revoke_session(&self, session_id: &str) -> Result<(), ApiError> {
+        let family_id = query!(
+            "SELECT family_id FROM refresh_tokens WHERE session_id = $1",
+            session_id
+        )
+        .fetch_optional(&self.db)
+        .await?;
+        if let Some(record) = family_id {
+            self.revoke_family(&record.family_id).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn revoke_family(&self, family_id: &str) -> Result<(), ApiError> {
+        let members = query!(
+            "DELETE FROM refresh_tokens WHERE family_id = $1 RETURNING user_id, session_id",
+            family_id
+        )
+        .fetch_all(&self.db)
+        .await?;
+        for member in members {
+            if let Some(session_id) = member.session_id {
+                self.tokens
+                    .revoke_session(&member.user_id, &session_id)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn delete_expired(&self) -> Result<u64, ApiError> {
+        let result = query!("DELETE FROM refresh_tokens WHERE expiry < now()")
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middlewares::tests::{context, create_user, delete_user, random_name};
+    use crate::tokens::Token;
+    use chrono::Duration;
+
+    fn refresh_token(username: &str, family_id: &str, session_id: Option<String>) -> RefreshToken {
+        RefreshToken {
+            expiry: Utc::now() + Duration::days(1),
+            username: username.to_string(),
+            client_id: None,
+            scope: None,
+            family_id: family_id.to_string(),
+            session_id,
+            attributes: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn replaying_a_rotated_token_revokes_its_family() {
+        let ctx = context().await;
+        let username = create_user(&ctx).await;
+        let token = Token::new(Utc::now() + Duration::minutes(10), username.clone());
+        let token_id = ctx.tokens.create(&token).await.unwrap();
+        let family_id = RefreshTokenStore::new_family_id();
+        let session_id = ctx.tokens.session_id(&token_id);
+        let first = ctx
+            .refresh_tokens
+            .create(&refresh_token(&username, &family_id, session_id.clone()))
+            .await
+            .unwrap();
+        let grant = ctx.refresh_tokens.redeem(&first, None).await.unwrap();
+        assert_eq!(grant.unwrap().family_id, family_id);
+        let second = ctx
+            .refresh_tokens
+            .create(&refresh_token(&username, &family_id, session_id))
+            .await
+            .unwrap();
+        assert!(ctx
+            .refresh_tokens
+            .redeem(&first, None)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(ctx
+            .refresh_tokens
+            .redeem(&second, None)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(ctx.tokens.read(&token_id).await.unwrap().is_none());
+        delete_user(&ctx, &username).await;
+    }
+
+    #[tokio::test]
+    async fn client_tokens_are_not_consumed_by_other_callers() {
+        let ctx = context().await;
+        let username = create_user(&ctx).await;
+        let client_id = random_name();
+        query!(
+            "INSERT INTO oauth2_clients (client_id, name, owner, redirect_uris, scope) VALUES ($1, $1, $2, '', '')",
+            client_id,
+            username
+        )
+        .execute(&ctx.db)
+        .await
+        .unwrap();
+        let family_id = RefreshTokenStore::new_family_id();
+        let token_id = ctx
+            .refresh_tokens
+            .create(&RefreshToken {
+                client_id: Some(client_id.clone()),
+                ..refresh_token(&username, &family_id, None)
+            })
+            .await
+            .unwrap();
+        assert!(ctx
+            .refresh_tokens
+            .redeem(&token_id, None)
+            .await
+            .unwrap()
+            .is_none());
+        let other_client = random_name();
+        let grant = ctx
+            .refresh_tokens
+            .redeem(&token_id, Some(&other_client))
+            .await
+            .unwrap();
+        assert!(grant.is_none());
+        let grant = ctx
+            .refresh_tokens
+            .redeem(&token_id, Some(&client_id))
+            .await
+            .unwrap();
+        assert_eq!(grant.unwrap().client_id, Some(client_id));
+        delete_user(&ctx, &username).await;
+    }
+}